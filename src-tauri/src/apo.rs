@@ -0,0 +1,745 @@
+use std::{fs, io, path::{Path, PathBuf}};
+
+use crate::structs::{ApoImport, ChannelLayout, ComparisonSlot, EqualiserChannelSettings, EqualiserSettings, Filter, FilterType, FilterWidth, GraphicEqPoint, SpatialSettings, Speaker};
+
+
+
+pub const MANAGED_FILE: &str = "slyshmefx.txt";
+// one config per comparison slot, the managed file only includes the playing one so switching is a single line
+const SLOT_FILES: [&str; 2] = ["slyshmefx-a.txt", "slyshmefx-b.txt"];
+const BACKUP_FILE: &str = "config.slyshmefx-backup.txt";
+const GUARD_START: &str = "# >>> SlyshMeFX, managed automatically, remove through the app";
+const GUARD_END: &str = "# <<< SlyshMeFX";
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+// the speakers a Channel line selects and those of them given by number
+type ChannelSelection = (Vec<Speaker>, Vec<Speaker>);
+// each output of a Copy line with the gain it takes from every input
+type Copies = Vec<(String, Vec<(String, f32)>)>;
+
+struct ParserState {
+    channels: Vec<EqualiserChannelSettings>, // every speaker, the layout is picked once the config is read
+    selected: Vec<Speaker>,
+    named: Vec<Speaker>, // speakers selected by name or number, "all" doesn't tell which ones exist
+    numbered: Vec<Speaker>, // the ones selected by number, taken in a 7.1 device's order until the config is read
+    spatial: Option<SpatialSettings>, // read back from the Copy lines the app writes
+    crossfeed: CrossfeedState,
+    warnings: Vec<String>,
+}
+impl ParserState {
+    fn selected(&mut self) -> Vec<&mut EqualiserChannelSettings> {
+        let selected = &self.selected;
+        self.channels.iter_mut().filter(|c| selected.contains(&c.speaker)).collect()
+    }
+
+    // Only the front pair's routing and the crossfeed SpatialSettings::toConfig writes are understood
+    fn copy(&mut self, copies: &Copies) -> Result<(), String> {
+        let outputs: Vec<&str> = copies.iter().map(|(output, _)| output.as_str()).collect();
+        let gain = |output: &str, input: &str| {
+            copies.iter().filter(|(o, _)| o == output).flat_map(|(_, terms)| terms).filter(|(i, _)| i == input).map(|(_, gain)| gain).sum::<f32>()
+        };
+        let only = |inputs: &[&str]| copies.iter().flat_map(|(_, terms)| terms).all(|(input, _)| inputs.contains(&input.as_str()));
+
+        match outputs[..] {
+            ["L", "R"] | ["R", "L"] if only(&["L", "R"]) => {
+                if self.spatial.is_some_and(|s| s.routingMatrix() != [[1., 0.], [0., 1.]]) {
+                    return Err("only one routing of the front pair is kept".into());
+                }
+                let matrix = [[gain("L", "L"), gain("L", "R")], [gain("R", "L"), gain("R", "R")]];
+                let routed = SpatialSettings::fromRoutingMatrix(matrix).ok_or("routing that isn't a width, balance or swap")?;
+
+                let spatial = self.spatial.get_or_insert_with(SpatialSettings::default);
+                (spatial.balance, spatial.width, spatial.mono, spatial.swap) = (routed.balance, routed.width, routed.mono, routed.swap);
+            },
+            ["XL", "XR"] if gain("XL", "R") == 1. && gain("XR", "L") == 1. && only(&["L", "R"]) && copies.iter().all(|(_, terms)| terms.len() == 1) => {
+                self.crossfeed = CrossfeedState::Copied;
+            },
+            ["L", "R"] if self.crossfeed != CrossfeedState::None && only(&["L", "R", "XL", "XR"]) => {
+                let (direct, cross) = (gain("L", "L"), gain("L", "XL"));
+                if direct <= 0. || cross <= 0. || gain("R", "R") != direct || gain("R", "XR") != cross {
+                    return Err("crossfeed that isn't the same for both sides".into());
+                }
+
+                let spatial = self.spatial.get_or_insert_with(SpatialSettings::default);
+                spatial.crossfeed = true;
+                // the gains are written normalised, their ratio is the level, rounded off like the slider sets it
+                spatial.crossfeedLevel = (2000. * (cross / direct).log10()).round() / 100.;
+                self.crossfeed = CrossfeedState::None;
+            },
+            _ => return Err("only the front pair's routing and crossfeed are supported".into()),
+        }
+
+        Ok(())
+    }
+}
+
+// where the crossfeed block SpatialSettings::toConfig writes is up to
+#[derive(Clone, Copy, PartialEq)]
+enum CrossfeedState {
+    None,
+    Copied, // XL and XR hold the opposite channels
+    Selected, // and are selected for their low pass and delay
+}
+
+// Parses an Equalizer APO config.txt, includes are resolved relative to `baseDir` just like APO does.
+// The layout is the smallest one with every speaker the config selects.
+pub fn parseConfig(contents: &str, baseDir: &Path) -> ApoImport {
+    let mut state = ParserState {
+        channels: Speaker::ALL.iter().map(|&speaker| EqualiserChannelSettings { filters: vec![], ..EqualiserChannelSettings::new(speaker) }).collect(),
+        selected: Speaker::ALL.to_vec(),
+        named: vec![],
+        numbered: vec![],
+        spatial: None,
+        crossfeed: CrossfeedState::None,
+        warnings: vec![],
+    };
+
+    parseInto(&mut state, contents, "config.txt", baseDir, 0);
+    renumber(&mut state);
+    if state.crossfeed != CrossfeedState::None {
+        state.warnings.push("the crossfeed isn't mixed back into L and R, it was left out".into());
+    }
+
+    let layout = ChannelLayout::covering(state.named.iter().copied());
+    let mut settings = EqualiserSettings::fromChannels(layout, state.channels);
+    settings.spatial = state.spatial.unwrap_or_default();
+
+    ApoImport { settings, warnings: state.warnings }
+}
+
+// Channel numbers follow the device's own order, which the config doesn't say. Like CamillaDSP imports the highest
+// number tells: up to 6 it's a 5.1 device, whose side pair is at 5 and 6 where a 7.1 device has its back pair.
+fn renumber(state: &mut ParserState) {
+    let highest = state.numbered.iter().filter_map(|&s| ChannelLayout::Surround71.position(s)).max().map_or(0, |i| i + 1);
+    // the front pair, centre and LFE are numbered alike either way
+    if highest < 5 {
+        return;
+    }
+
+    let sides = [Speaker::BackLeft, Speaker::BackRight, Speaker::SideLeft, Speaker::SideRight];
+    if highest > 6 {
+        state.warnings.push(format!("channels are numbered up to {}, taken to be a 7.1 device's", highest));
+    } else if state.named.iter().any(|s| sides.contains(s) && !state.numbered.contains(s)) {
+        state.warnings.push("channels 5 and 6 are taken to be a 7.1 device's back speakers, side or back speakers are named as well".into());
+    } else {
+        let renumbered = |speaker: Speaker| match speaker {
+            Speaker::BackLeft => Speaker::SideLeft,
+            Speaker::BackRight => Speaker::SideRight,
+            Speaker::SideLeft => Speaker::BackLeft,
+            Speaker::SideRight => Speaker::BackRight,
+            speaker => speaker,
+        };
+        state.channels.iter_mut().for_each(|c| c.speaker = renumbered(c.speaker));
+        state.named.iter_mut().for_each(|s| *s = renumbered(*s));
+        state.warnings.push(format!("channels are numbered up to {}, taken to be a 5.1 device's with the side speakers at 5 and 6", highest));
+    }
+}
+
+// Writes to a sibling temp file first so APO never picks up a half written config
+pub fn writeAtomic(path: &Path, contents: &str) -> io::Result<()> {
+    let tempPath = path.with_extension("slyshmefx-tmp");
+
+    fs::write(&tempPath, contents)?;
+    fs::rename(&tempPath, path)
+}
+
+// Adds the guarded include of our managed file to config.txt, backing up the original the first time
+pub fn installInclude(configDir: &Path) -> io::Result<()> {
+    let configPath = configDir.join("config.txt");
+    let config = match fs::read_to_string(&configPath) {
+        Ok(config) => config,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    if config.contains(GUARD_START) {
+        return Ok(());
+    }
+
+    let backupPath = configDir.join(BACKUP_FILE);
+    if !backupPath.exists() {
+        writeAtomic(&backupPath, &config)?;
+    }
+
+    let separator = if config.is_empty() || config.ends_with('\n') { "" } else { "\n" };
+    writeAtomic(
+        &configPath,
+        &format!("{}{}{}\nInclude: {}\n{}\n", config, separator, GUARD_START, MANAGED_FILE, GUARD_END),
+    )
+}
+
+fn slotPath(configDir: &Path, slot: ComparisonSlot) -> PathBuf {
+    configDir.join(SLOT_FILES[slot as usize])
+}
+
+// Writes a comparison slot's config, leaving the file alone when it already holds it
+pub fn writeSlot(configDir: &Path, slot: ComparisonSlot, contents: &str) -> io::Result<()> {
+    installInclude(configDir)?;
+
+    let path = slotPath(configDir, slot);
+    if fs::read_to_string(&path).is_ok_and(|current| current == contents) {
+        return Ok(());
+    }
+    writeAtomic(&path, contents)
+}
+
+// Points the managed file at a slot's config, or at nothing to bypass the equaliser
+pub fn selectSlot(configDir: &Path, slot: Option<ComparisonSlot>) -> io::Result<()> {
+    let contents = match slot {
+        Some(slot) => format!("Include: {}\n", SLOT_FILES[slot as usize]),
+        None => "# bypassed\n".into(),
+    };
+
+    writeAtomic(&configDir.join(MANAGED_FILE), &contents)
+}
+
+// Removes everything the app added, optionally putting the config.txt from before the first install back
+pub fn uninstall(configDir: &Path, restoreBackup: bool) -> io::Result<()> {
+    let configPath = configDir.join("config.txt");
+    let backupPath = configDir.join(BACKUP_FILE);
+
+    if restoreBackup && backupPath.exists() {
+        writeAtomic(&configPath, &fs::read_to_string(&backupPath)?)?;
+        fs::remove_file(&backupPath)?;
+    } else if let Ok(config) = fs::read_to_string(&configPath) {
+        let mut inGuard = false;
+        let stripped: String = config
+            .lines()
+            .filter(|line| {
+                let line = line.trim();
+                if line == GUARD_START {
+                    inGuard = true;
+                } else if line == GUARD_END {
+                    inGuard = false;
+                    return false;
+                }
+
+                !inGuard
+            })
+            .map(|line| format!("{}\n", line))
+            .collect();
+
+        writeAtomic(&configPath, &stripped)?;
+    }
+
+    for file in SLOT_FILES.iter().chain([&MANAGED_FILE]) {
+        match fs::remove_file(configDir.join(file)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {},
+        }
+    }
+
+    Ok(())
+}
+
+fn parseInto(state: &mut ParserState, contents: &str, fileName: &str, baseDir: &Path, depth: usize) {
+    let mut conditionalDepth = 0;
+
+    for (i, rawLine) in contents.lines().enumerate() {
+        let line = rawLine.trim();
+        let location = format!("{}:{}", fileName, i + 1);
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((command, argument)) = line.split_once(':') else {
+            state.warnings.push(format!("{}: not a command, ignored: {}", location, line));
+            continue;
+        };
+        let argument = argument.trim();
+
+        // commands may be numbered or labelled, e.g. "Filter 3:" or "Filter1:"
+        let command = command.split_whitespace().next().unwrap_or("");
+        let command = command.trim_end_matches(|c: char| c.is_ascii_digit()).to_ascii_lowercase();
+
+        if conditionalDepth > 0 {
+            match command.as_str() {
+                "if" => conditionalDepth += 1,
+                "endif" => conditionalDepth -= 1,
+                _ => {}
+            }
+            continue;
+        }
+
+        // the crossfeed's virtual channels only take its low pass and delay
+        if state.crossfeed == CrossfeedState::Selected {
+            match command.as_str() {
+                "filter" => match parseFilter(argument) {
+                    Ok(Some(filter)) if filter.filterType == FilterType::LowPass => {
+                        state.spatial.get_or_insert_with(SpatialSettings::default).crossfeedCutoff = filter.frequency;
+                        continue;
+                    },
+                    _ => {
+                        state.warnings.push(format!("{}: only a low pass is supported on the crossfeed, ignored: {}", location, line));
+                        continue;
+                    },
+                },
+                "delay" => {
+                    if parseDelay(argument).map_or(true, |(delay, _)| delay != SpatialSettings::CROSSFEED_DELAY_MS) {
+                        state.warnings.push(format!("{}: the crossfeed delay is fixed at {} ms, ignored: {}", location, SpatialSettings::CROSSFEED_DELAY_MS, line));
+                    }
+                    continue;
+                },
+                _ => state.crossfeed = CrossfeedState::Copied,
+            }
+        }
+
+        match command.as_str() {
+            "preamp" => match parseNumber(argument.split_whitespace().next()) {
+                Some(gain) => state.selected().into_iter().for_each(|c| c.preamp += gain),
+                None => state.warnings.push(format!("{}: invalid preamp, ignored: {}", location, line)),
+            },
+            "filter" => match parseFilter(argument) {
+                Ok(Some(filter)) => state.selected().into_iter().for_each(|c| c.filters.push(filter)),
+                Ok(None) => {},
+                Err(e) => state.warnings.push(format!("{}: {}, ignored: {}", location, e, line)),
+            },
+            "graphiceq" => match parseGraphicEq(argument) {
+                Ok(points) => {
+                    let mut replaced = false;
+                    for channel in state.selected() {
+                        replaced |= !channel.graphicEq.is_empty();
+                        channel.graphicEq = points.clone();
+                    }
+
+                    if replaced {
+                        state.warnings.push(format!("{}: only one graphic EQ per channel is kept, the earlier one was replaced", location));
+                    }
+                },
+                Err(e) => state.warnings.push(format!("{}: {}, ignored: {}", location, e, line)),
+            },
+            "convolution" => {
+                // relative paths are resolved like includes, the response and the backends need absolute ones
+                let path = baseDir.join(argument).to_string_lossy().into_owned();
+                state.selected().into_iter().for_each(|c| c.convolution = Some(path.clone()));
+            },
+            "channel" if state.crossfeed == CrossfeedState::Copied && argument.split_whitespace().map(str::to_ascii_uppercase).eq(["XL", "XR"]) => {
+                state.selected = vec![];
+                state.crossfeed = CrossfeedState::Selected;
+            },
+            "copy" => {
+                if let Err(e) = parseCopy(argument).and_then(|copies| state.copy(&copies)) {
+                    state.warnings.push(format!("{}: {}, ignored: {}", location, e, line));
+                }
+            },
+            "channel" => match parseChannels(argument) {
+                Ok(Some((speakers, numbered))) => {
+                    state.named.extend(&speakers);
+                    state.numbered.extend(numbered);
+                    state.selected = speakers;
+                },
+                Ok(None) => state.selected = Speaker::ALL.to_vec(),
+                Err(e) => {
+                    state.warnings.push(format!("{}: {}: {}", location, e, line));
+                    state.selected = vec![];
+                }
+            },
+            "delay" => match parseDelay(argument) {
+                Ok((delay, warning)) => {
+                    state.selected().into_iter().for_each(|c| c.delay += delay);
+                    if let Some(warning) = warning {
+                        state.warnings.push(format!("{}: {}", location, warning));
+                    }
+                },
+                Err(e) => state.warnings.push(format!("{}: {}, ignored: {}", location, e, line)),
+            },
+            "include" => {
+                let path = baseDir.join(argument);
+
+                if depth >= MAX_INCLUDE_DEPTH {
+                    state.warnings.push(format!("{}: includes nested too deeply, ignored: {}", location, line));
+                } else {
+                    match std::fs::read_to_string(&path) {
+                        Ok(included) => {
+                            // APO restores the channel selection after an include
+                            let selected = state.selected.clone();
+                            let includeDir = path.parent().unwrap_or(baseDir).to_path_buf();
+
+                            parseInto(state, &included, argument, &includeDir, depth + 1);
+                            state.selected = selected;
+                        },
+                        Err(e) => state.warnings.push(format!("{}: could not read include ({}), ignored: {}", location, e, line)),
+                    }
+                }
+            },
+            "if" => {
+                conditionalDepth = 1;
+                state.warnings.push(format!("{}: conditional blocks are not supported, their contents were skipped: {}", location, line));
+            },
+            "device" | "stage" | "eval" | "loadplugin" | "vstplugin" => {
+                state.warnings.push(format!("{}: {} is not supported, ignored: {}", location, command, line));
+            },
+            "elseif" | "else" | "endif" => {
+                state.warnings.push(format!("{}: {} without a matching if, ignored", location, command));
+            },
+            _ => state.warnings.push(format!("{}: unknown command, ignored: {}", location, line)),
+        }
+    }
+}
+
+fn parseNumber(token: Option<&str>) -> Option<f32> {
+    token?.replace(',', ".").parse().ok().filter(|n: &f32| n.is_finite())
+}
+
+// frequencies may be given in kHz with a k suffix, "Fc 1k Hz" or "Fc 2,5k Hz"
+fn parseFrequency(token: Option<&str>) -> Option<f32> {
+    let token = token?;
+
+    match token.strip_suffix(['k', 'K']) {
+        Some(kilo) => parseNumber(Some(kilo)).map(|f| f * 1000.),
+        None => parseNumber(Some(token)),
+    }
+}
+
+// "GraphicEQ: 20 -1.5; 21 -1.4; ..." as written by AutoEq and APO's editor, points sorted by frequency
+fn parseGraphicEq(argument: &str) -> Result<Vec<GraphicEqPoint>, String> {
+    let mut points = vec![];
+
+    for pair in argument.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+        let mut tokens = pair.split_whitespace();
+        let (Some(frequency), Some(gain)) = (parseNumber(tokens.next()), parseNumber(tokens.next())) else {
+            return Err(format!("invalid graphic EQ point {}", pair));
+        };
+        if frequency <= 0. {
+            return Err(format!("invalid graphic EQ frequency {}", frequency));
+        }
+
+        points.push(GraphicEqPoint { frequency, gain });
+    }
+
+    if points.is_empty() {
+        return Err("graphic EQ without points".into());
+    }
+    points.sort_by(|a, b| a.frequency.total_cmp(&b.frequency));
+
+    Ok(points)
+}
+
+// None selects every channel.
+// Numbers count the speakers in the order a 7.1 device has them, `renumber` fixes them up for 5.1 ones.
+fn parseChannels(argument: &str) -> Result<Option<ChannelSelection>, String> {
+    let mut speakers = vec![];
+    let mut numbered = vec![];
+
+    for token in argument.split_whitespace() {
+        let token = token.to_ascii_uppercase();
+        if token == "ALL" {
+            return Ok(None);
+        }
+
+        let speaker = match token.parse::<usize>() {
+            Ok(number) => {
+                let speaker = ChannelLayout::Surround71.speakers().get(number.wrapping_sub(1)).copied();
+                numbered.extend(speaker);
+                speaker
+            },
+            Err(_) => Speaker::ALL.into_iter().find(|s| s.apoName() == token),
+        };
+        match speaker {
+            Some(speaker) => speakers.push(speaker),
+            None => return Err(format!("channel {} is not supported, following commands are ignored until the next channel selection", token)),
+        }
+    }
+
+    Ok(Some((speakers, numbered)))
+}
+
+// "L=0.75*L+0.25*R R=1.25*L-0.25*R" or "L=R", a bare input has a gain of 1 and "L=0" takes nothing
+fn parseCopy(argument: &str) -> Result<Copies, String> {
+    let mut copies = vec![];
+
+    for assignment in argument.split_whitespace() {
+        let (output, expression) = assignment.split_once('=').ok_or_else(|| format!("invalid copy {}", assignment))?;
+        let mut terms = vec![];
+
+        for term in expression.replace('-', "+-").split('+').filter(|t| !t.is_empty()) {
+            let (gain, input) = match term.split_once('*') {
+                Some((gain, input)) => (parseNumber(Some(gain)).ok_or_else(|| format!("invalid copy gain {}", gain))?, input),
+                None if parseNumber(Some(term)) == Some(0.) => continue,
+                None => match term.strip_prefix('-') {
+                    Some(input) => (-1., input),
+                    None => (1., term),
+                },
+            };
+            if input.is_empty() || parseNumber(Some(input)).is_some() {
+                return Err(format!("invalid copy term {}", term));
+            }
+
+            terms.push((input.to_ascii_uppercase(), gain));
+        }
+
+        copies.push((output.to_ascii_uppercase(), terms));
+    }
+
+    if copies.is_empty() {
+        return Err("copy without channels".into());
+    }
+
+    Ok(copies)
+}
+
+// "Delay: 10 ms" or "Delay: 480 samples", returning milliseconds and a warning when it had to be converted
+fn parseDelay(argument: &str) -> Result<(f32, Option<String>), String> {
+    let mut tokens = argument.split_whitespace();
+    let delay = parseNumber(tokens.next()).filter(|&d| d >= 0.).ok_or("invalid delay")?;
+
+    match tokens.next().map(str::to_ascii_lowercase).as_deref() {
+        Some("ms") | None => Ok((delay, None)),
+        Some("samples") => Ok((delay / 48., Some("delays in samples are taken to be at 48 kHz".into()))),
+        Some(unit) => Err(format!("delay unit {} is not supported", unit)),
+    }
+}
+
+// Ok(None) means the filter line was valid but has no effect worth keeping (APO's "None" type)
+fn parseFilter(argument: &str) -> Result<Option<Filter>, String> {
+    let tokens: Vec<&str> = argument.split_whitespace().collect();
+
+    let enabled = match tokens.first().map(|t| t.to_ascii_uppercase()).as_deref() {
+        Some("ON") => true,
+        Some("OFF") => false,
+        _ => return Err("missing ON/OFF".into()),
+    };
+    let typeName = tokens.get(1).ok_or("missing filter type")?.to_ascii_uppercase();
+    if !matches!(typeName.as_str(), "NONE" | "PK" | "PEQ" | "MODAL" | "LP" | "HP" | "LPQ" | "HPQ" | "BP" | "LS" | "LSC" | "HS" | "HSC" | "NO" | "AP") {
+        return Err(format!("filter type {} is not supported", typeName));
+    }
+
+    let mut frequency = None;
+    let mut gain = None;
+    let mut q = None;
+    let mut bandwidth = None;
+    let mut slope = None;
+
+    // a value that is there but unreadable is reported as such, a missing one is reported once the type says it's needed
+    let value = |name: &str, token: Option<&str>, parse: fn(Option<&str>) -> Option<f32>| match token {
+        Some(token) => parse(Some(token)).map(Some).ok_or_else(|| format!("invalid {} value '{}'", name, token)),
+        None => Ok(None),
+    };
+
+    let mut i = 2;
+    while i < tokens.len() {
+        let next = tokens.get(i + 1).copied();
+
+        match tokens[i].to_ascii_uppercase().as_str() {
+            "FC" => {
+                frequency = value("Fc", next, parseFrequency)?;
+                i += 1;
+            },
+            "GAIN" => {
+                gain = value("Gain", next, parseNumber)?;
+                i += 1;
+            },
+            "Q" => {
+                q = value("Q", next, parseNumber)?;
+                i += 1;
+            },
+            "BW" => {
+                // "BW Oct 1.5"
+                bandwidth = value("BW", tokens.get(i + 2).copied(), parseNumber)?;
+                i += 2;
+            },
+            "HZ" | "DB" => {},
+            token => {
+                // a bare number followed by dB right after the type is a shelf slope, "LSC 12 dB" or "LS 6dB"
+                if let Some(value) = parseNumber(Some(token.trim_end_matches("DB"))) {
+                    slope = Some(value);
+                } else {
+                    return Err(format!("unexpected {}", tokens[i]));
+                }
+            },
+        }
+
+        i += 1;
+    }
+
+    let width = |defaultQ: f32| match (q, bandwidth) {
+        (Some(q), _) => FilterWidth::Q(q),
+        (None, Some(bw)) => FilterWidth::Q(bandwidthToQ(bw)),
+        (None, None) => FilterWidth::Q(defaultQ),
+    };
+    let shelfWidth = |defaultQ: f32| match (q, slope) {
+        (None, Some(slope)) => FilterWidth::Slope(slope),
+        _ => width(defaultQ),
+    };
+
+    let (filterType, width) = match typeName.as_str() {
+        "NONE" => return Ok(None),
+        "PK" | "PEQ" | "MODAL" => (FilterType::Peak, width(1.0)),
+        "LP" => (FilterType::LowPass, FilterWidth::Q(0.707)),
+        "HP" => (FilterType::HighPass, FilterWidth::Q(0.707)),
+        "LPQ" => (FilterType::LowPass, width(0.707)),
+        "HPQ" => (FilterType::HighPass, width(0.707)),
+        "BP" => (FilterType::BandPass, width(1.0)),
+        "LS" | "LSC" => (FilterType::LowShelf, shelfWidth(0.707)),
+        "HS" | "HSC" => (FilterType::HighShelf, shelfWidth(0.707)),
+        "NO" => (FilterType::Notch, width(30.0)),
+        "AP" => (FilterType::AllPass, width(0.707)),
+        _ => unreachable!(),
+    };
+
+    let frequency = frequency.ok_or("missing Fc")?;
+    let gain = match filterType {
+        FilterType::Peak | FilterType::LowShelf | FilterType::HighShelf => gain.ok_or("missing Gain")?,
+        _ => 0.0,
+    };
+
+    Ok(Some(Filter {
+        filterType,
+        frequency,
+        gain,
+        width,
+        enabled,
+    }))
+}
+
+pub fn bandwidthToQ(octaves: f32) -> f32 {
+    let power = octaves.exp2();
+    power.sqrt() / (power - 1.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> ApoImport {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/apo");
+        parseConfig(&fs::read_to_string(dir.join(name)).unwrap(), &dir)
+    }
+
+    fn peak(frequency: f32, gain: f32, q: f32) -> Filter {
+        Filter { filterType: FilterType::Peak, frequency, gain, width: FilterWidth::Q(q), enabled: true }
+    }
+
+    #[test]
+    fn basic() {
+        let import = fixture("basic.txt");
+        assert_eq!(import.warnings, Vec::<String>::new());
+
+        let settings = import.settings;
+        assert_eq!(settings.layout, ChannelLayout::Stereo);
+        assert!(settings.linked);
+        assert_eq!(settings.channels[0].preamp, -6.5);
+        assert_eq!(settings.channels[0].filters, vec![
+            Filter { filterType: FilterType::LowShelf, frequency: 105., gain: 5.5, width: FilterWidth::Q(0.71), enabled: true },
+            peak(250., -2., 1.41),
+            Filter { enabled: false, ..peak(3000., 4., bandwidthToQ(1.)) },
+            Filter { filterType: FilterType::HighShelf, frequency: 8000., gain: -3., width: FilterWidth::Slope(12.), enabled: true },
+            Filter { filterType: FilterType::HighPass, frequency: 20., gain: 0., width: FilterWidth::Q(0.707), enabled: true },
+        ]);
+        assert_eq!(settings.channels[0].graphicEq, vec![
+            GraphicEqPoint { frequency: 20., gain: 1.5 },
+            GraphicEqPoint { frequency: 1000., gain: 0. },
+            GraphicEqPoint { frequency: 20_000., gain: -4. },
+        ]);
+        assert_eq!(settings.channels[1], EqualiserChannelSettings { speaker: Speaker::FrontRight, ..settings.channels[0].clone() });
+    }
+
+    #[test]
+    fn includes() {
+        let import = fixture("include.txt");
+
+        let settings = import.settings;
+        assert!(!settings.linked);
+        // the include's own selection ends with it, the filter after it is the left channel's again
+        assert_eq!(settings.channels[0].filters, vec![peak(1000., 1., 1.)]);
+        assert_eq!(settings.channels[1].filters, vec![peak(60., -8., 6.)]);
+        // nested includes resolve relative to the including file
+        assert_eq!((settings.channels[0].delay, settings.channels[1].delay), (0., 2.5));
+        assert!(settings.channels.iter().all(|c| c.preamp == -3.));
+
+        assert_eq!(import.warnings.len(), 1);
+        assert!(import.warnings[0].starts_with("config.txt:5: could not read include ("), "{}", import.warnings[0]);
+        assert!(import.warnings[0].ends_with("ignored: Include: include/missing.txt"), "{}", import.warnings[0]);
+    }
+
+    #[test]
+    fn channelSelection() {
+        let import = fixture("channels.txt");
+        assert_eq!(import.warnings, vec![
+            "config.txt:8: delays in samples are taken to be at 48 kHz",
+            "config.txt:9: channel TOP is not supported, following commands are ignored until the next channel selection: Channel: TOP",
+        ]);
+
+        let settings = import.settings;
+        assert_eq!(settings.layout, ChannelLayout::Surround51);
+        let channel = |speaker| settings.channel(speaker).unwrap();
+        assert_eq!(channel(Speaker::FrontLeft).filters, vec![peak(100., 3., 1.)]);
+        assert_eq!(channel(Speaker::FrontRight).filters, vec![peak(100., 3., 1.), peak(2000., -1., 2.)]);
+        assert_eq!((channel(Speaker::Centre).preamp, channel(Speaker::Lfe).preamp), (-6., -6.));
+        assert_eq!((channel(Speaker::SideLeft).delay, channel(Speaker::SideRight).delay), (10., 10.));
+        // the preamp after the unsupported selection went nowhere
+        assert!(settings.channels.iter().all(|c| c.preamp > -20.));
+    }
+
+    // numbers only reaching 6 are a 5.1 device's, whose 5 and 6 are the side pair
+    #[test]
+    fn numberedChannels() {
+        let import = fixture("numbered.txt");
+        assert_eq!(import.warnings, vec!["channels are numbered up to 6, taken to be a 5.1 device's with the side speakers at 5 and 6"]);
+
+        let settings = import.settings;
+        assert_eq!(settings.layout, ChannelLayout::Surround51);
+        let channel = |speaker| settings.channel(speaker).unwrap();
+        assert_eq!(channel(Speaker::FrontRight).filters, vec![peak(100., 2., 1.)]);
+        assert_eq!(channel(Speaker::Centre).preamp, -4.);
+        assert_eq!(channel(Speaker::Lfe).filters[0].filterType, FilterType::LowPass);
+        assert_eq!((channel(Speaker::SideLeft).delay, channel(Speaker::SideRight).delay), (5., 5.));
+        assert_eq!(channel(Speaker::FrontLeft).delay, 0.);
+
+        // 7 and 8 only exist on 7.1 devices
+        let import = parseConfig("Channel: 5 6\nDelay: 2 ms\nChannel: 7 8\nDelay: 1 ms\n", Path::new("."));
+        assert_eq!(import.warnings, vec!["channels are numbered up to 8, taken to be a 7.1 device's"]);
+        assert_eq!(import.settings.layout, ChannelLayout::Surround71);
+        assert_eq!(import.settings.channel(Speaker::BackLeft).unwrap().delay, 2.);
+        assert_eq!(import.settings.channel(Speaker::SideRight).unwrap().delay, 1.);
+
+        // named side speakers keep the numbers a 7.1 device's
+        let import = parseConfig("Channel: SL SR\nPreamp: -1 dB\nChannel: 5 6\nDelay: 2 ms\n", Path::new("."));
+        assert_eq!(import.warnings, vec!["channels 5 and 6 are taken to be a 7.1 device's back speakers, side or back speakers are named as well"]);
+        assert_eq!(import.settings.layout, ChannelLayout::Surround71);
+        assert_eq!(import.settings.channel(Speaker::BackRight).unwrap().delay, 2.);
+    }
+
+    #[test]
+    fn unsupportedCommands() {
+        let import = fixture("unsupported.txt");
+        assert_eq!(import.warnings, vec![
+            "config.txt:2: device is not supported, ignored: Device: Speakers",
+            "config.txt:3: only the front pair's routing and crossfeed are supported, ignored: Copy: C=0.5*L+0.5*R",
+            "config.txt:4: conditional blocks are not supported, their contents were skipped: If: sampleRate == 44100",
+            "config.txt:10: endif without a matching if, ignored",
+            "config.txt:11: vstplugin is not supported, ignored: VSTPlugin: Library \"a.dll\"",
+            "config.txt:12: unknown command, ignored: Loudness: ON",
+        ]);
+
+        // nothing inside the conditional blocks was applied
+        let settings = import.settings;
+        assert!(settings.linked);
+        assert_eq!(settings.channels[0].preamp, -1.);
+        assert_eq!(settings.channels[0].filters, vec![peak(500., 2., 1.)]);
+    }
+
+    #[test]
+    fn malformedLines() {
+        let import = fixture("malformed.txt");
+        assert_eq!(import.warnings, vec![
+            "config.txt:1: not a command, ignored: Preamp -3 dB",
+            "config.txt:2: invalid preamp, ignored: Preamp: loud",
+            "config.txt:3: missing Fc, ignored: Filter: ON PK Gain 2 dB Q 1",
+            "config.txt:4: filter type XYZ is not supported, ignored: Filter: ON XYZ Fc 100 Hz",
+            "config.txt:5: missing ON/OFF, ignored: Filter: PK Fc 100 Hz Gain 1 dB Q 1",
+            "config.txt:6: invalid graphic EQ point 1000, ignored: GraphicEQ: 20 1; 1000",
+            "config.txt:7: invalid graphic EQ frequency -20, ignored: GraphicEQ: -20 1",
+            "config.txt:8: delay unit parsecs is not supported, ignored: Delay: 10 parsecs",
+            "config.txt:9: invalid Fc value '1kHz', ignored: Filter: ON PK Fc 1kHz Gain 1 dB Q 1",
+            "config.txt:10: invalid Gain value 'loud', ignored: Filter: ON PK Fc 100 Hz Gain loud dB Q 1",
+        ]);
+
+        // decimal commas and kHz suffixes are read like APO reads them
+        let settings = import.settings;
+        assert_eq!(settings.channels[0].preamp, 0.);
+        assert_eq!(settings.channels[0].filters, vec![peak(1000., 1., 1.), peak(440., 1.5, 2.), peak(2500., -2., 1.)]);
+        assert!(settings.channels[0].graphicEq.is_empty());
+    }
+}
@@ -0,0 +1,706 @@
+use std::{fs, io, path::{Path, PathBuf}, process::Command, sync::Arc, thread, time::Duration};
+
+use tauri::{menu::{CheckMenuItem, Menu, MenuItem}, AppHandle, Emitter, Manager, Wry};
+
+use crate::{apo, convolution, pipewire::{self, FilterChain}, presets::findPreset, structs::{AppConfig, ComparisonSlot, ComparisonStatus, DynamicsSupport, EqualiserOverrides, EqualiserPreset, EqualiserSettings, EqualiserStatus, PipeWireFilter}, util::{defaultOutputDevice, defaultOutputDeviceName, defaultOutputSampleRate, readRegistryString, registrySubKeys}};
+
+
+
+const APO_REGISTRY_KEY: &str = "SOFTWARE\\EqualizerAPO";
+const APO_UNINSTALL_KEY: &str = "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall\\EqualizerAPO";
+const APO_DEFAULT_INSTALL_DIR: &str = "C:/Program Files/EqualizerAPO";
+// what most devices mix at, used when the default one can't be asked
+const FALLBACK_SAMPLE_RATE: f64 = 48_000.;
+// long enough for a slider being dragged, short enough not to be noticed after letting go
+const PIPEWIRE_RESTART_DEBOUNCE: Duration = Duration::from_millis(300);
+pub const TRAY_ID: &str = "main";
+pub const TRAY_BYPASS_ID: &str = "bypass";
+pub const TRAY_COMPARISON_ID: &str = "toggleAB";
+
+// the config location override a backend was discovered with, and the backend
+pub type DiscoveredBackend = (Option<String>, Arc<dyn EqualiserBackend>);
+
+pub trait EqualiserBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+    // where generated configs go and includes are resolved from
+    fn configDir(&self) -> &Path;
+    // cheap check used before every write, `status` does the thorough one
+    fn isAvailable(&self) -> bool;
+    fn status(&self) -> EqualiserStatus;
+    // the dynamics stages it can run, `apply` leaves the others out
+    fn dynamicsSupport(&self) -> DynamicsSupport;
+    // settings of the supported stages it can't run as set, described for the UI
+    fn clampedSettings(&self, settings: &EqualiserSettings) -> Vec<String>;
+    fn apply(&self, settings: &EqualiserSettings) -> io::Result<()>;
+    // Makes a comparison slot holding `settings` audible, no slot bypasses the equaliser
+    fn switchTo(&self, slot: Option<ComparisonSlot>, settings: &EqualiserSettings) -> io::Result<()>;
+    fn uninstall(&self, restoreBackup: bool) -> io::Result<()>;
+}
+
+pub struct ApoBackend {
+    pub installDir: PathBuf,
+    pub configDir: PathBuf,
+    pub version: Option<String>,
+    pub devices: Vec<String>,
+}
+impl ApoBackend {
+    // The override wins over the registry, which wins over the default install location
+    pub fn discover(configDirOverride: Option<&Path>) -> Self {
+        Self::locate(
+            configDirOverride,
+            readRegistryString(APO_REGISTRY_KEY, "InstallPath").map(PathBuf::from),
+            readRegistryString(APO_REGISTRY_KEY, "ConfigPath").map(PathBuf::from),
+            readRegistryString(APO_UNINSTALL_KEY, "DisplayVersion"),
+            registrySubKeys(&format!("{}\\Child APOs", APO_REGISTRY_KEY)),
+        )
+    }
+
+    pub fn locate(
+        configDirOverride: Option<&Path>,
+        installDir: Option<PathBuf>,
+        configDir: Option<PathBuf>,
+        version: Option<String>,
+        devices: Vec<String>,
+    ) -> Self {
+        let installDir = installDir.unwrap_or_else(|| PathBuf::from(APO_DEFAULT_INSTALL_DIR));
+        let configDir = match configDirOverride {
+            Some(configDir) => configDir.to_path_buf(),
+            None => configDir.unwrap_or_else(|| installDir.join("config")),
+        };
+
+        Self {
+            installDir,
+            configDir,
+            version,
+            devices,
+        }
+    }
+}
+impl EqualiserBackend for ApoBackend {
+    fn name(&self) -> &'static str {
+        "EqualizerAPO"
+    }
+
+    fn configDir(&self) -> &Path {
+        &self.configDir
+    }
+
+    fn isAvailable(&self) -> bool {
+        self.configDir.is_dir()
+    }
+
+    fn status(&self) -> EqualiserStatus {
+        EqualiserStatus {
+            backend: self.name().into(),
+            installed: self.installDir.join("EqualizerAPO.dll").is_file() || self.configDir.join("config.txt").is_file(),
+            version: self.version.clone(),
+            configDir: self.configDir.to_string_lossy().into(),
+            writable: isWritable(&self.configDir),
+            devices: self.devices.clone(),
+            dynamics: self.dynamicsSupport(),
+            seamlessSwitching: true,
+        }
+    }
+
+    // APO has no dynamics of its own, only through VST plugins we can't count on
+    fn dynamicsSupport(&self) -> DynamicsSupport {
+        DynamicsSupport::default()
+    }
+
+    fn clampedSettings(&self, _settings: &EqualiserSettings) -> Vec<String> {
+        vec![]
+    }
+
+    // the slot is rewritten either way, a bypassed equaliser stays bypassed
+    fn apply(&self, settings: &EqualiserSettings) -> io::Result<()> {
+        let (slot, playing) = {
+            let comparison = crate::EQUALISER_COMPARISON.read().unwrap();
+            (comparison.slot, comparison.playing())
+        };
+        apo::writeSlot(&self.configDir, slot, &settings.toConfig(outputSampleRate()))?;
+        apo::selectSlot(&self.configDir, playing)
+    }
+
+    // Each slot keeps its own file, so once both are written switching only rewrites the include
+    fn switchTo(&self, slot: Option<ComparisonSlot>, settings: &EqualiserSettings) -> io::Result<()> {
+        if let Some(slot) = slot {
+            apo::writeSlot(&self.configDir, slot, &settings.toConfig(outputSampleRate()))?;
+        }
+
+        apo::selectSlot(&self.configDir, slot)
+    }
+
+    fn uninstall(&self, restoreBackup: bool) -> io::Result<()> {
+        apo::uninstall(&self.configDir, restoreBackup)
+    }
+}
+
+pub struct PipeWireBackend {
+    pub configDir: PathBuf,
+    pub version: Option<String>,
+    pub dynamics: DynamicsSupport,
+}
+impl PipeWireBackend {
+    pub fn discover(configDirOverride: Option<&Path>) -> Self {
+        Self::locate(
+            configDirOverride,
+            std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from),
+            std::env::var_os("HOME").map(PathBuf::from),
+            Command::new("pipewire").arg("--version").output().ok().map(|output| String::from_utf8_lossy(&output.stdout).into_owned()),
+            pipewire::dynamicsSupport(),
+        )
+    }
+
+    // The override wins over $XDG_CONFIG_HOME/pipewire, which wins over ~/.config/pipewire.
+    // `versionOutput` is what `pipewire --version` printed, None when it couldn't be run.
+    pub fn locate(
+        configDirOverride: Option<&Path>,
+        configHome: Option<PathBuf>,
+        home: Option<PathBuf>,
+        versionOutput: Option<String>,
+        dynamics: DynamicsSupport,
+    ) -> Self {
+        let configDir = match configDirOverride {
+            Some(configDir) => configDir.to_path_buf(),
+            None => configHome
+                .or_else(|| home.map(|home| home.join(".config")))
+                .unwrap_or_default()
+                .join("pipewire"),
+        };
+        // "pipewire\nCompiled with libpipewire 1.0.5\nLinked with libpipewire 1.0.5"
+        let version = versionOutput.and_then(|output| output.lines().find_map(|l| l.strip_prefix("Linked with libpipewire ").map(str::to_string)));
+
+        Self {
+            configDir,
+            version,
+            dynamics,
+        }
+    }
+
+    fn configPath(&self) -> PathBuf {
+        self.configDir.join(pipewire::CONFIG_FILE)
+    }
+
+    // Changes only to the controls are made in place. Filter-chain modules can't change their graph,
+    // so otherwise the filter's own PipeWire client is restarted with the new config once the changes settle.
+    fn run(&self, chain: &FilterChain) -> io::Result<()> {
+        fs::create_dir_all(&self.configDir)?;
+        apo::writeAtomic(&self.configPath(), &chain.config())?;
+
+        let topology = chain.topology();
+        let mut filter = crate::PIPEWIRE_FILTER.lock().unwrap();
+        let running = filter.process.as_mut().is_some_and(|p| matches!(p.try_wait(), Ok(None)));
+        // a pending restart reads the config just written
+        if filter.topology.as_ref() == Some(&topology) && (filter.restarting || (running && setControls(chain))) {
+            return Ok(());
+        }
+
+        filter.topology = Some(topology);
+        filter.restarts += 1;
+        filter.restarting = true;
+        let (restart, configPath) = (filter.restarts, self.configPath());
+
+        thread::spawn(move || {
+            thread::sleep(PIPEWIRE_RESTART_DEBOUNCE);
+
+            let mut filter = crate::PIPEWIRE_FILTER.lock().unwrap();
+            if filter.restarts != restart {
+                return;
+            }
+
+            stopFilter(&mut filter);
+            match Command::new("pipewire").arg("-c").arg(&configPath).spawn() {
+                Ok(process) => filter.process = Some(process),
+                Err(e) => {
+                    eprintln!("Failed to start the PipeWire filter-chain: {}", e);
+                    filter.topology = None;
+                },
+            }
+            filter.restarting = false;
+        });
+
+        Ok(())
+    }
+}
+
+// Stops the filter-chain and cancels a pending restart, it's the app's own child and shouldn't outlive it
+pub fn stopFilterChain() {
+    let mut filter = crate::PIPEWIRE_FILTER.lock().unwrap();
+    filter.restarts += 1;
+    filter.restarting = false;
+    filter.topology = None;
+
+    stopFilter(&mut filter);
+}
+
+fn stopFilter(filter: &mut PipeWireFilter) {
+    if let Some(mut process) = filter.process.take() {
+        let _ = process.kill();
+        let _ = process.wait();
+    }
+}
+
+// Sets the running filter-chain's controls, false when pw-cli couldn't and it has to be restarted instead
+fn setControls(chain: &FilterChain) -> bool {
+    Command::new("pw-cli")
+        .arg("set-param")
+        .arg(pipewire::SINK_NODE)
+        .arg("Props")
+        .arg(pipewire::propsParam(&chain.controls()))
+        .output()
+        // pw-cli reports unknown objects and bad params on stderr without failing
+        .is_ok_and(|output| output.status.success() && output.stderr.is_empty())
+}
+
+impl EqualiserBackend for PipeWireBackend {
+    fn name(&self) -> &'static str {
+        "PipeWire"
+    }
+
+    fn configDir(&self) -> &Path {
+        &self.configDir
+    }
+
+    fn isAvailable(&self) -> bool {
+        self.version.is_some()
+    }
+
+    fn status(&self) -> EqualiserStatus {
+        EqualiserStatus {
+            backend: self.name().into(),
+            installed: self.version.is_some(),
+            version: self.version.clone(),
+            configDir: self.configDir.to_string_lossy().into(),
+            writable: fs::create_dir_all(&self.configDir).is_ok() && isWritable(&self.configDir),
+            devices: vec![],
+            dynamics: self.dynamics,
+            // switches that only change controls are made in place, the others restart the filter
+            seamlessSwitching: false,
+        }
+    }
+
+    fn dynamicsSupport(&self) -> DynamicsSupport {
+        self.dynamics
+    }
+
+    fn clampedSettings(&self, settings: &EqualiserSettings) -> Vec<String> {
+        pipewire::clampedDynamics(&settings.dynamics.limitedTo(&self.dynamics))
+    }
+
+    // a missing plugin would fail the whole filter-chain, so stages without one are left out
+    fn apply(&self, settings: &EqualiserSettings) -> io::Result<()> {
+        let settings = EqualiserSettings {
+            dynamics: settings.dynamics.limitedTo(&self.dynamics),
+            ..settings.clone()
+        };
+
+        self.run(&pipewire::filterChain(&settings, outputSampleRate()))
+    }
+
+    // there's only ever one graph, slots sharing its topology switch in place but bypassing restarts it
+    fn switchTo(&self, slot: Option<ComparisonSlot>, settings: &EqualiserSettings) -> io::Result<()> {
+        match slot {
+            Some(_) => self.apply(settings),
+            None => self.run(&pipewire::bypassChain(settings.layout)),
+        }
+    }
+
+    fn uninstall(&self, _restoreBackup: bool) -> io::Result<()> {
+        stopFilterChain();
+
+        match fs::remove_file(self.configPath()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn isWritable(dir: &Path) -> bool {
+    let probe = dir.join(".slyshmefx-write-test");
+    let writable = fs::write(&probe, []).is_ok();
+    let _ = fs::remove_file(&probe);
+
+    writable
+}
+
+pub fn outputSampleRate() -> f64 {
+    defaultOutputSampleRate().map_or(FALLBACK_SAMPLE_RATE, f64::from)
+}
+
+fn discoverBackend(configDirOverride: Option<&Path>) -> Arc<dyn EqualiserBackend> {
+    if cfg!(target_os = "linux") {
+        Arc::new(PipeWireBackend::discover(configDirOverride))
+    } else {
+        Arc::new(ApoBackend::discover(configDirOverride))
+    }
+}
+
+// Discovery spawns processes and reads the registry, so its result is kept until the config location changes
+pub fn currentBackend() -> Arc<dyn EqualiserBackend> {
+    let configDirOverride = crate::EQUALISER_BACKEND_CONFIG.read().unwrap().configDirOverride.clone();
+    let mut cached = crate::EQUALISER_BACKEND.lock().unwrap();
+
+    match cached.as_ref() {
+        Some((location, backend)) if *location == configDirOverride => backend.clone(),
+        _ => {
+            let backend = discoverBackend(configDirOverride.as_deref().map(Path::new));
+            *cached = Some((configDirOverride, backend.clone()));
+            backend
+        },
+    }
+}
+
+// Discovers the backend again, picking up installs and plugins added while running
+pub fn refreshBackend() -> Arc<dyn EqualiserBackend> {
+    *crate::EQUALISER_BACKEND.lock().unwrap() = None;
+    currentBackend()
+}
+
+// Normalises new settings, writes them through the current backend and persists them, returning what was applied
+pub fn applyEqualiserSettings(appHandle: &AppHandle, settings: EqualiserSettings) -> Result<EqualiserSettings, String> {
+    let settings = settings.synchronised().withAutoHeadroom(outputSampleRate());
+
+    for channel in &settings.channels {
+        if let Some(path) = &channel.convolution {
+            let ir = convolution::load(Path::new(path))?;
+            // APO can't pick one channel out of a multichannel IR for a single channel section
+            if ir.channels.len() > 1 && !settings.isLinked() {
+                return Err("Multichannel impulse responses need linked channels, use a mono one per channel instead".into());
+            }
+        }
+    }
+
+    let _switching = crate::EQUALISER_SWITCH.lock().unwrap();
+    let backend = currentBackend();
+    if backend.isAvailable() {
+        // while bypassed the backend stays bypassed, the settings are written to their slot once it's brought back
+        let playing = crate::EQUALISER_COMPARISON.read().unwrap().playing();
+        backend.switchTo(playing, &settings).map_err(|e| e.to_string())?;
+    }
+
+    // the stages are kept in the settings either way, the UI shows which of them aren't heard
+    let unsupported = settings.dynamics.unsupported(&backend.dynamicsSupport());
+    let _ = appHandle.emit("equaliserUnsupported", serde_json::to_string(&unsupported).unwrap());
+    let _ = appHandle.emit("equaliserClamped", serde_json::to_string(&backend.clampedSettings(&settings)).unwrap());
+
+    *crate::EQUALISER_CONFIG.write().unwrap() = settings.clone();
+    AppConfig::current().save(appHandle).map_err(|e| e.to_string())?;
+
+    Ok(settings)
+}
+
+// Applies a preset, linked ones on the speakers already in use
+pub fn applyPreset(appHandle: &AppHandle, preset: EqualiserPreset) -> Result<EqualiserSettings, String> {
+    let current = crate::EQUALISER_CONFIG.read().unwrap().clone();
+    applyEqualiserSettings(appHandle, preset.settings.onSpeakersOf(&current))
+}
+
+// Tray entries for bypassing and switching slots, rebuilt whenever the comparison changes
+pub fn trayMenu(appHandle: &AppHandle, status: &ComparisonStatus) -> tauri::Result<Menu<Wry>> {
+    let bypass = CheckMenuItem::with_id(appHandle, TRAY_BYPASS_ID, "Bypass equaliser", true, status.bypassed, None::<&str>)?;
+    let switch = MenuItem::with_id(appHandle, TRAY_COMPARISON_ID, format!("Switch to {:?}", status.slot.other()), true, None::<&str>)?;
+
+    Menu::with_items(appHandle, &[&bypass, &switch])
+}
+
+fn emitComparison(appHandle: &AppHandle) {
+    let status = crate::EQUALISER_COMPARISON.read().unwrap().status();
+
+    let _ = appHandle.emit("equaliserComparison", serde_json::to_string(&status).unwrap());
+    if let Some(tray) = appHandle.tray_by_id(TRAY_ID) {
+        let _ = tray.set_menu(trayMenu(appHandle, &status).ok());
+    }
+}
+
+// Bypasses the equaliser or brings the playing slot back, the settings themselves are kept either way
+pub fn setBypassed(appHandle: &AppHandle, bypassed: bool) -> Result<ComparisonStatus, String> {
+    let _switching = crate::EQUALISER_SWITCH.lock().unwrap();
+    let slot = crate::EQUALISER_COMPARISON.read().unwrap().slot;
+
+    let backend = currentBackend();
+    if backend.isAvailable() {
+        let settings = crate::EQUALISER_CONFIG.read().unwrap().clone();
+        backend.switchTo((!bypassed).then_some(slot), &settings).map_err(|e| e.to_string())?;
+    }
+
+    crate::EQUALISER_COMPARISON.write().unwrap().bypassed = bypassed;
+    AppConfig::current().save(appHandle).map_err(|e| e.to_string())?;
+    emitComparison(appHandle);
+
+    Ok(crate::EQUALISER_COMPARISON.read().unwrap().status())
+}
+
+// Swaps the playing settings with the other slot's, the first switch starts B out as a copy of A
+pub fn toggleComparison(appHandle: &AppHandle) -> Result<EqualiserSettings, String> {
+    let _switching = crate::EQUALISER_SWITCH.lock().unwrap();
+    let comparison = crate::EQUALISER_COMPARISON.read().unwrap().clone();
+    let playing = crate::EQUALISER_CONFIG.read().unwrap().clone();
+    let next = comparison.stored.unwrap_or_else(|| playing.clone());
+    let slot = comparison.slot.other();
+
+    let backend = currentBackend();
+    if backend.isAvailable() {
+        backend.switchTo(Some(slot), &next).map_err(|e| e.to_string())?;
+    }
+
+    {
+        let mut comparison = crate::EQUALISER_COMPARISON.write().unwrap();
+        comparison.slot = slot;
+        comparison.stored = Some(playing);
+        comparison.bypassed = false;
+    }
+    *crate::EQUALISER_CONFIG.write().unwrap() = next.clone();
+    AppConfig::current().save(appHandle).map_err(|e| e.to_string())?;
+
+    let _ = appHandle.emit("equaliserUpdate", serde_json::to_string(&next).unwrap());
+    emitComparison(appHandle);
+
+    Ok(next)
+}
+
+// Applies whichever preset overrides the user's own settings, remembering those so they're put back once none does.
+// Returns None when there was nothing to put back.
+fn applyOverrides(appHandle: &AppHandle) -> Result<Option<EqualiserSettings>, String> {
+    let preset = crate::EQUALISER_OVERRIDES.read().unwrap().preset().cloned();
+
+    let applied = match preset {
+        Some(name) => {
+            crate::BASE_EQUALISER
+                .write()
+                .unwrap()
+                .get_or_insert_with(|| crate::EQUALISER_CONFIG.read().unwrap().clone());
+
+            let dir = appHandle.path().app_local_data_dir().map_err(|e| e.to_string())?;
+            let preset = findPreset(&dir, &name, outputSampleRate()).map_err(|e| format!("Failed to apply preset {}: {}", name, e))?;
+            applyPreset(appHandle, preset)?
+        },
+        None => {
+            // taken before applying, saving the config reads it
+            let own = crate::BASE_EQUALISER.write().unwrap().take();
+            let Some(own) = own else {
+                return Ok(None);
+            };
+
+            applyEqualiserSettings(appHandle, own)?
+        },
+    };
+
+    let _ = appHandle.emit("equaliserUpdate", serde_json::to_string(&applied).unwrap());
+    Ok(Some(applied))
+}
+
+// Changes what overrides the user's settings, the device's and the application profile's preset are set apart so
+// either going away falls back to the other or to the user's own settings. Only applies when the preset heard changes.
+pub fn updateOverrides(appHandle: &AppHandle, update: impl FnOnce(&mut EqualiserOverrides)) {
+    let (before, after) = {
+        let mut overrides = crate::EQUALISER_OVERRIDES.write().unwrap();
+        let before = overrides.preset().cloned();
+        update(&mut overrides);
+
+        (before, overrides.preset().cloned())
+    };
+
+    if before != after {
+        if let Err(e) = applyOverrides(appHandle) {
+            eprintln!("Failed to apply the equaliser override: {}", e);
+        }
+    }
+}
+
+// Follows the default output device, applying the preset bound to it whenever the device or its binding changes.
+// Devices without a binding get the user's own settings back.
+pub fn watchOutputDevice(appHandle: AppHandle) {
+    let mut lastBinding: Option<(Option<String>, Option<String>)> = None;
+
+    loop {
+        let device = defaultOutputDevice();
+        let preset = device
+            .as_ref()
+            .and_then(|d| crate::EQUALISER_BACKEND_CONFIG.read().unwrap().deviceBindings.get(d).cloned());
+        let binding = (device, preset);
+
+        if lastBinding.as_ref() != Some(&binding) {
+            *crate::OUTPUT_DEVICE.write().unwrap() = binding.0.clone();
+            updateOverrides(&appHandle, |overrides| overrides.device = binding.1.clone());
+
+            if let Some(tray) = appHandle.tray_by_id(TRAY_ID) {
+                let tooltip = match &binding.1 {
+                    Some(preset) => format!("SlyshMeFX\n{}: {}", defaultOutputDeviceName().unwrap_or("Output".into()), preset),
+                    None => "SlyshMeFX".into(),
+                };
+                let _ = tray.set_tooltip(Some(tooltip));
+            }
+
+            lastBinding = Some(binding);
+        }
+
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a scratch install tree, removed when dropped
+    struct FakeInstall(PathBuf);
+    impl FakeInstall {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("slyshmefx-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+
+            Self(dir)
+        }
+    }
+    impl Drop for FakeInstall {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn notInstalled() {
+        let install = FakeInstall::new("apo-missing");
+        let backend = ApoBackend::locate(None, Some(install.0.join("EqualizerAPO")), None, None, vec![]);
+
+        assert_eq!(backend.configDir, install.0.join("EqualizerAPO").join("config"));
+        assert!(!backend.isAvailable());
+        let status = backend.status();
+        assert!(!status.installed);
+        assert!(!status.writable);
+    }
+
+    #[test]
+    fn installed() {
+        let install = FakeInstall::new("apo-installed");
+        fs::create_dir_all(install.0.join("config")).unwrap();
+        fs::write(install.0.join("EqualizerAPO.dll"), []).unwrap();
+        let devices = vec!["{0.0.0.00000000}.{speakers}".to_string()];
+        let backend = ApoBackend::locate(None, Some(install.0.clone()), None, Some("1.3.2".into()), devices.clone());
+
+        assert!(backend.isAvailable());
+        let status = backend.status();
+        assert!(status.installed);
+        assert!(status.writable);
+        assert_eq!(status.version.as_deref(), Some("1.3.2"));
+        assert_eq!(status.devices, devices);
+    }
+
+    // installed but not set up on any device yet, APO is there and configs can still be written
+    #[test]
+    fn missingDevices() {
+        let install = FakeInstall::new("apo-no-devices");
+        let configDir = install.0.join("custom-config");
+        fs::create_dir_all(&configDir).unwrap();
+        fs::write(configDir.join("config.txt"), "").unwrap();
+        let backend = ApoBackend::locate(None, Some(install.0.clone()), Some(configDir.clone()), None, vec![]);
+
+        assert_eq!(backend.configDir, configDir);
+        let status = backend.status();
+        assert!(status.installed);
+        assert!(status.writable);
+        assert!(status.devices.is_empty());
+        assert_eq!(status.version, None);
+    }
+
+    #[test]
+    fn overrideWins() {
+        let install = FakeInstall::new("apo-override");
+        let configDir = install.0.join("override");
+        fs::create_dir_all(&configDir).unwrap();
+        let backend = ApoBackend::locate(Some(&configDir), Some(install.0.clone()), Some(install.0.join("registry")), None, vec![]);
+
+        assert_eq!(backend.configDir, configDir);
+        assert!(backend.isAvailable());
+        // neither the DLL nor a config.txt, so it's only a directory
+        assert!(!backend.status().installed);
+    }
+
+    #[test]
+    fn readOnly() {
+        let install = FakeInstall::new("apo-read-only");
+        let configDir = install.0.join("config");
+        fs::create_dir_all(&configDir).unwrap();
+        fs::write(configDir.join("config.txt"), "").unwrap();
+
+        let mut permissions = fs::metadata(&configDir).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&configDir, permissions.clone()).unwrap();
+        // administrators and root write through read-only directories, there's nothing to check then
+        let privileged = fs::write(configDir.join("probe"), []).is_ok();
+
+        let status = ApoBackend::locate(None, Some(install.0.clone()), None, None, vec![]).status();
+
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(&configDir, permissions).unwrap();
+
+        assert!(status.installed);
+        assert_eq!(status.writable, privileged);
+    }
+
+    // applying while bypassed keeps the slot up to date without bringing the equaliser back
+    #[test]
+    fn applyKeepsTheBypass() {
+        let install = FakeInstall::new("apo-bypassed");
+        let configDir = install.0.join("config");
+        fs::create_dir_all(&configDir).unwrap();
+        let backend = ApoBackend::locate(Some(&configDir), None, None, None, vec![]);
+
+        crate::EQUALISER_COMPARISON.write().unwrap().bypassed = true;
+        let applied = backend.apply(&EqualiserSettings::default());
+        crate::EQUALISER_COMPARISON.write().unwrap().bypassed = false;
+
+        applied.unwrap();
+        assert_eq!(fs::read_to_string(configDir.join(apo::MANAGED_FILE)).unwrap(), "# bypassed\n");
+        assert!(configDir.join("slyshmefx-a.txt").exists());
+    }
+
+    const PIPEWIRE_VERSION: &str = "pipewire\nCompiled with libpipewire 1.0.5\nLinked with libpipewire 1.2.7\n";
+
+    #[test]
+    fn pipeWireConfigLocations() {
+        let install = FakeInstall::new("pipewire-locations");
+        let (configHome, home, configDir) = (install.0.join("xdg"), install.0.join("home"), install.0.join("override"));
+        let locate = |configDirOverride: Option<&Path>, configHome: Option<&Path>| {
+            PipeWireBackend::locate(configDirOverride, configHome.map(Path::to_path_buf), Some(home.clone()), None, DynamicsSupport::default()).configDir
+        };
+
+        assert_eq!(locate(None, None), home.join(".config").join("pipewire"));
+        assert_eq!(locate(None, Some(&configHome)), configHome.join("pipewire"));
+        assert_eq!(locate(Some(&configDir), Some(&configHome)), configDir);
+    }
+
+    #[test]
+    fn pipeWireInstalled() {
+        let install = FakeInstall::new("pipewire-installed");
+        let dynamics = DynamicsSupport { compressor: true, limiter: false };
+        let backend = PipeWireBackend::locate(None, Some(install.0.clone()), None, Some(PIPEWIRE_VERSION.into()), dynamics);
+
+        assert!(backend.isAvailable());
+        let status = backend.status();
+        assert!(status.installed);
+        // the library actually loaded, not the one it was built against
+        assert_eq!(status.version.as_deref(), Some("1.2.7"));
+        assert_eq!(status.dynamics, dynamics);
+        // the config directory is made when it's missing
+        assert!(status.writable);
+        assert!(install.0.join("pipewire").is_dir());
+    }
+
+    #[test]
+    fn pipeWireMissing() {
+        let install = FakeInstall::new("pipewire-missing");
+
+        // not on the PATH, or something else answering to the name
+        for versionOutput in [None, Some("bash: pipewire: command not found\n".to_string())] {
+            let backend = PipeWireBackend::locate(None, Some(install.0.clone()), None, versionOutput, DynamicsSupport::default());
+
+            assert!(!backend.isAvailable());
+            let status = backend.status();
+            assert!(!status.installed);
+            assert_eq!(status.version, None);
+        }
+    }
+}
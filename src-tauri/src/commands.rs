@@ -1,14 +1,49 @@
 use std::{fs, process::Command, sync::atomic::Ordering, thread};
 use tauri::{AppHandle, Emitter, Manager, PhysicalPosition};
 
-use crate::{structs::{AppConfig, EqualiserSettings, VisualiserSettings}};
-use super::util::audioCapture;
+use crate::{apo, backend::{self, applyEqualiserSettings, currentBackend, outputSampleRate}, convolution, fitting::{fitFilters, FilterFit}, formats, measurement, presets, response::settingsResponse, structs::{ApoImport, AppConfig, AppProfileSettings, ChannelLayout, ComparisonStatus, EqualiserBackendSettings, EqualiserChannelSettings, EqualiserExport, EqualiserFormat, EqualiserPreset, EqualiserResponse, EqualiserSettings, EqualiserStatus, FitLimits, GraphicEqImport, GraphicEqPoint, ImpulseResponseInfo, MeasurementMatch, Speaker, TargetCurve, VisualiserSettings}};
+use super::util::{audioCapture, defaultOutputDevice, latencyCompensation};
 use tauri_plugin_wallpaper::{AttachRequest, WallpaperExt};
 
 
 
+#[tauri::command]
+pub fn applyPreset(appHandle: AppHandle, name: String) -> Result<EqualiserSettings, String> {
+    let preset = presets::findPreset(&presetsDir(&appHandle)?, &name, outputSampleRate()).map_err(|e| e.to_string())?;
+
+    // picked by hand while a device's or an application profile's preset is applied, it's the user's own choice
+    if let Some(base) = crate::BASE_EQUALISER.write().unwrap().as_mut() {
+        *base = preset.settings.clone().onSpeakersOf(base);
+    }
+    backend::applyPreset(&appHandle, preset)
+}
+
+#[tauri::command]
+pub fn bypassEqualiser(appHandle: AppHandle, bypassed: bool) -> Result<ComparisonStatus, String> {
+    backend::setBypassed(&appHandle, bypassed)
+}
+
+#[tauri::command]
+pub fn calibrateLatency(appHandle: AppHandle, beatTimes: Vec<f64>, tapTimes: Vec<f64>) -> Result<u16, String> {
+    let device = defaultOutputDevice().ok_or("No output device found")?;
+    let delay = latencyCompensation(&beatTimes, &tapTimes).ok_or("Not enough taps to calibrate")?;
+
+    crate::VISUALISER_CONFIG.write().unwrap().latencyCompensation.insert(device.clone(), delay);
+    if let Some(base) = crate::PROFILE_BASE_VISUALISER.write().unwrap().as_mut() {
+        base.latencyCompensation.insert(device, delay);
+    }
+
+    let config = AppConfig::current();
+    appHandle.emit("visualiserUpdate", serde_json::to_string(&config.visualiserSettings).unwrap()).unwrap();
+    config.save(&appHandle).map_err(|e| e.to_string())?;
+
+    Ok(delay)
+}
+
 #[tauri::command]
 pub fn close(appHandle: AppHandle, restart: bool) {
+    // restarting skips the exit event
+    backend::stopFilterChain();
     if restart {
         appHandle.restart();
     } else {
@@ -17,8 +52,52 @@ pub fn close(appHandle: AppHandle, restart: bool) {
 }
 
 #[tauri::command]
-pub fn getConfigs() -> Result<(EqualiserSettings, VisualiserSettings), String> {
-    Ok((*crate::EQUALISER_CONFIG.read().unwrap(), crate::VISUALISER_CONFIG.read().unwrap().clone()))
+pub fn deletePreset(appHandle: AppHandle, name: String) -> Result<(), String> {
+    presets::deletePreset(&presetsDir(&appHandle)?, &name).map_err(|e| e.to_string())?;
+
+    crate::EQUALISER_BACKEND_CONFIG.write().unwrap().deviceBindings.retain(|_, preset| *preset != name);
+    AppConfig::current().save(&appHandle).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn exportEqualiserSettings(format: EqualiserFormat) -> Result<EqualiserExport, String> {
+    let settings = crate::EQUALISER_CONFIG.read().unwrap().clone();
+
+    Ok(match format {
+        EqualiserFormat::EasyEffects => formats::toEasyEffects(&settings, outputSampleRate()),
+        EqualiserFormat::CamillaDsp => formats::toCamillaDsp(&settings, outputSampleRate()),
+    })
+}
+
+#[tauri::command]
+pub fn exportPreset(appHandle: AppHandle, name: String) -> Result<String, String> {
+    let preset = presets::findPreset(&presetsDir(&appHandle)?, &name, outputSampleRate()).map_err(|e| e.to_string())?;
+    serde_json::to_string_pretty(&EqualiserPreset { builtIn: false, ..preset }).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn getComparisonStatus() -> Result<ComparisonStatus, String> {
+    Ok(crate::EQUALISER_COMPARISON.read().unwrap().status())
+}
+
+#[tauri::command]
+pub fn getConfigs() -> Result<(EqualiserSettings, VisualiserSettings, EqualiserBackendSettings, AppProfileSettings), String> {
+    let config = AppConfig::current();
+    Ok((config.equaliserSettings, config.visualiserSettings, config.equaliserBackendSettings, config.appProfileSettings))
+}
+
+#[tauri::command]
+pub fn getEqualiserResponse(sampleRate: f32, points: u16) -> Result<EqualiserResponse, String> {
+    if sampleRate <= 0. {
+        return Err("Sample rate must be positive".into());
+    }
+
+    Ok(settingsResponse(&crate::EQUALISER_CONFIG.read().unwrap(), points.into(), sampleRate.into()))
+}
+
+#[tauri::command]
+pub fn getEqualiserStatus() -> Result<EqualiserStatus, String> {
+    Ok(backend::refreshBackend().status())
 }
 
 #[tauri::command]
@@ -27,6 +106,16 @@ pub fn getMonitors(appHandle: AppHandle) -> Result<String, String> {
     Ok(serde_json::to_string(&monitors).expect("Failed to serialise available monitors."))
 }
 
+#[tauri::command]
+pub fn getOutputDevice() -> Result<String, String> {
+    defaultOutputDevice().ok_or("No output device found".into())
+}
+
+#[tauri::command]
+pub fn getPresets(appHandle: AppHandle) -> Result<Vec<EqualiserPreset>, String> {
+    presets::allPresets(&presetsDir(&appHandle)?, outputSampleRate()).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub async fn getWallpaper() -> Result<Vec<u8>, String> {
     match wallpaper::get() {
@@ -58,21 +147,149 @@ pub fn hideSettingsUi(appHandle: AppHandle) -> Result<(), ()> {
 }
 
 #[tauri::command]
-pub fn setEqualiserSettings(appHandle: AppHandle, newSettings: String) -> Result<(), ()> {
-    let settings: EqualiserSettings = serde_json::from_str(&newSettings).unwrap();
-    let config = settings.toConfig();
+pub fn importApoConfig(contents: String) -> Result<ApoImport, String> {
+    Ok(apo::parseConfig(&contents, currentBackend().configDir()))
+}
 
-    if fs::exists("C:/Program Files/EqualizerAPO/config/config.txt").unwrap() {
-        fs::write("C:/Program Files/EqualizerAPO/config/config.txt", config).unwrap();
+#[tauri::command]
+pub fn importEqualiserSettings(format: EqualiserFormat, contents: String) -> Result<ApoImport, String> {
+    match format {
+        EqualiserFormat::EasyEffects => formats::fromEasyEffects(&contents),
+        EqualiserFormat::CamillaDsp => formats::fromCamillaDsp(&contents),
     }
-    
-    *crate::EQUALISER_CONFIG.write().unwrap() = settings;
-    AppConfig {
-        visualiserSettings: crate::VISUALISER_CONFIG.read().unwrap().clone(),
-        equaliserSettings: settings,
-    }.save(&appHandle).unwrap();
+}
 
-    Ok(())
+// APO syntax covers AutoEq's ParametricEQ.txt and GraphicEQ.txt, graphic EQ curves are optionally fitted to `bands` peaking filters
+#[tauri::command]
+pub fn importEqualiserProfile(contents: String, graphicEq: GraphicEqImport, bands: u8) -> Result<ApoImport, String> {
+    let mut import = apo::parseConfig(&contents, currentBackend().configDir());
+
+    for channel in &mut import.settings.channels {
+        if channel.graphicEq.is_empty() {
+            continue;
+        }
+
+        // graphic EQ profiles carry no preamp of their own
+        channel.autoHeadroom = true;
+        if graphicEq == GraphicEqImport::Fit {
+            let FilterFit { filters, level, error } = fitFilters(&channel.graphicEq, bands.into(), &FitLimits::default(), outputSampleRate());
+            import.warnings.push(format!(
+                "{} channel: graphic EQ fitted with {} filters, {:.2} dB RMS deviation",
+                channel.speaker.short(),
+                filters.len(),
+                error
+            ));
+
+            // auto headroom normalises it again, it matters once that's turned off
+            channel.preamp += level;
+            channel.filters.extend(filters);
+            channel.graphicEq.clear();
+        }
+    }
+
+    import.settings = EqualiserSettings::fromChannels(import.settings.layout, import.settings.channels).withAutoHeadroom(outputSampleRate());
+
+    Ok(import)
+}
+
+#[tauri::command]
+pub fn importPreset(appHandle: AppHandle, contents: String) -> Result<EqualiserPreset, String> {
+    let dir = presetsDir(&appHandle)?;
+    let mut preset: EqualiserPreset = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+
+    // never silently replace a preset that's already in the library
+    preset.name = presets::unusedName(&dir, &preset.name).map_err(|e| e.to_string())?;
+
+    presets::storePreset(&dir, preset.clone()).map_err(|e| e.to_string())?;
+    Ok(EqualiserPreset { builtIn: false, ..preset })
+}
+
+// Corrects a REW or CSV measurement towards `target` with `bands` peaking filters and saves the result as a preset
+// named `name`, suffixed like imports when that's taken
+#[tauri::command]
+pub fn matchMeasurement(appHandle: AppHandle, name: String, contents: String, target: TargetCurve, bands: u8, limits: FitLimits) -> Result<MeasurementMatch, String> {
+    let dir = presetsDir(&appHandle)?;
+    let (measured, mut warnings) = measurement::parseMeasurement(&contents)?;
+    let (filters, error) = measurement::matchTarget(&measured, &target, bands.into(), &limits, outputSampleRate());
+    warnings.push(format!("{} filters fitted, {:.2} dB RMS deviation from the target", filters.len(), error));
+
+    let channel = EqualiserChannelSettings { filters, autoHeadroom: true, ..EqualiserChannelSettings::new(Speaker::FrontLeft) };
+    let channels = vec![channel.clone(), EqualiserChannelSettings::new(Speaker::FrontRight).linkedTo(&channel)];
+    let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, channels).withAutoHeadroom(outputSampleRate());
+
+    let unused = presets::unusedName(&dir, &name).map_err(|e| e.to_string())?;
+    if unused != name.trim() {
+        warnings.push(format!("a preset named {} already exists, saved as {}", name.trim(), unused));
+    }
+    let preset = EqualiserPreset { name: unused, settings, builtIn: false };
+    presets::storePreset(&dir, preset.clone()).map_err(|e| e.to_string())?;
+
+    Ok(MeasurementMatch { preset, warnings })
+}
+
+// Custom target curves are written like measurements
+#[tauri::command]
+pub fn parseTargetCurve(contents: String) -> Result<Vec<GraphicEqPoint>, String> {
+    measurement::parseMeasurement(&contents).map(|(points, _)| points)
+}
+
+// Response of settings that haven't been applied yet, used to preview imports
+#[tauri::command]
+pub fn previewEqualiserResponse(newSettings: String, sampleRate: f32, points: u16) -> Result<EqualiserResponse, String> {
+    if sampleRate <= 0. {
+        return Err("Sample rate must be positive".into());
+    }
+
+    let settings: EqualiserSettings = serde_json::from_str(&newSettings).map_err(|e| e.to_string())?;
+    Ok(settingsResponse(&settings.synchronised().withAutoHeadroom(sampleRate.into()), points.into(), sampleRate.into()))
+}
+
+#[tauri::command]
+pub fn renamePreset(appHandle: AppHandle, name: String, newName: String) -> Result<(), String> {
+    presets::renamePreset(&presetsDir(&appHandle)?, &name, &newName).map_err(|e| e.to_string())?;
+
+    // device bindings follow the preset
+    for preset in crate::EQUALISER_BACKEND_CONFIG.write().unwrap().deviceBindings.values_mut() {
+        if *preset == name {
+            *preset = newName.trim().into();
+        }
+    }
+    AppConfig::current().save(&appHandle).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn savePreset(appHandle: AppHandle, name: String) -> Result<(), String> {
+    let settings = crate::EQUALISER_CONFIG.read().unwrap().clone();
+    presets::storePreset(&presetsDir(&appHandle)?, EqualiserPreset { name, settings, builtIn: false }).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn setAppProfileSettings(appHandle: AppHandle, newSettings: String) -> Result<(), String> {
+    let settings: AppProfileSettings = serde_json::from_str(&newSettings).map_err(|e| e.to_string())?;
+    *crate::APP_PROFILE_CONFIG.write().unwrap() = settings;
+
+    AppConfig::current().save(&appHandle).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn setEqualiserBackendSettings(appHandle: AppHandle, newSettings: String) -> Result<(), String> {
+    let settings: EqualiserBackendSettings = serde_json::from_str(&newSettings).map_err(|e| e.to_string())?;
+    *crate::EQUALISER_BACKEND_CONFIG.write().unwrap() = settings;
+
+    // the new location should reflect the current curve straight away, applied like any other change and saved with it
+    let current = crate::EQUALISER_CONFIG.read().unwrap().clone();
+    applyEqualiserSettings(&appHandle, current).map(|_| ())
+}
+
+#[tauri::command]
+pub fn setEqualiserSettings(appHandle: AppHandle, newSettings: String) -> Result<EqualiserSettings, String> {
+    let settings: EqualiserSettings = serde_json::from_str(&newSettings).map_err(|e| e.to_string())?;
+
+    // edits made while a device's or an application profile's preset is applied change the user's own settings
+    if let Some(base) = crate::BASE_EQUALISER.write().unwrap().as_mut() {
+        *base = settings.clone();
+    }
+    applyEqualiserSettings(&appHandle, settings)
 }
 
 #[tauri::command]
@@ -122,13 +339,14 @@ pub fn setVisualiserSettings(appHandle: AppHandle, newSettings: String) -> Resul
     let settings: VisualiserSettings = serde_json::from_str(&newSettings).unwrap();
     let lastMonitor = crate::VISUALISER_CONFIG.read().unwrap().screen.clone();
 
+    // edits made while an application profile is shown change the user's own settings
+    if let Some(base) = crate::PROFILE_BASE_VISUALISER.write().unwrap().as_mut() {
+        *base = settings.clone();
+    }
     *crate::VISUALISER_CONFIG.write().unwrap() = settings.clone();
 
     appHandle.emit("visualiserUpdate", newSettings).unwrap();
-    AppConfig {
-        visualiserSettings: settings.clone(),
-        equaliserSettings: *crate::EQUALISER_CONFIG.read().unwrap(),
-    }.save(&appHandle).unwrap();
+    AppConfig::current().save(&appHandle).unwrap();
 
     if lastMonitor != settings.screen {
         appHandle.emit("startScreenChange", settings.screen).unwrap();
@@ -163,3 +381,23 @@ pub fn startCapture(appHandle: AppHandle) -> Result<(), String> {
 
     Ok(())
 }
+
+// Switches between the two stored equaliser settings, returning the ones now playing
+#[tauri::command]
+pub fn toggleAB(appHandle: AppHandle) -> Result<EqualiserSettings, String> {
+    backend::toggleComparison(&appHandle)
+}
+
+#[tauri::command]
+pub fn uninstallEqualiser(restoreBackup: bool) -> Result<(), String> {
+    currentBackend().uninstall(restoreBackup).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn validateImpulseResponse(path: String) -> Result<ImpulseResponseInfo, String> {
+    convolution::info(std::path::Path::new(path.trim()), outputSampleRate() as u32)
+}
+
+fn presetsDir(appHandle: &AppHandle) -> Result<std::path::PathBuf, String> {
+    appHandle.path().app_local_data_dir().map_err(|e| e.to_string())
+}
@@ -0,0 +1,339 @@
+use std::{f64::consts::PI, fs, path::{Path, PathBuf}, sync::{Arc, Mutex, OnceLock}, time::SystemTime};
+
+use crate::structs::{ChannelLayout, ImpulseResponseInfo};
+
+
+
+const MIN_SAMPLE_RATE: u32 = 8_000;
+const MAX_SAMPLE_RATE: u32 = 384_000;
+// anything longer is almost certainly a recording picked by mistake, and costs a lot of CPU in APO
+const MAX_SECONDS: f64 = 10.;
+// short IRs are zero padded at least this far so the bins are close enough to interpolate between
+const MIN_SPECTRUM_SIZE: usize = 1 << 15;
+// one IR per speaker of the largest layout
+const MAX_CACHED: usize = 8;
+
+pub struct ImpulseResponse {
+    pub sampleRate: u32,
+    pub channels: Vec<Vec<f32>>,
+    // each channel's spectrum up to Nyquist, computed the first time it's asked for
+    spectra: Vec<OnceLock<Vec<(f32, f32)>>>,
+}
+impl ImpulseResponse {
+    pub fn new(sampleRate: u32, channels: Vec<Vec<f32>>) -> Self {
+        let spectra = channels.iter().map(|_| OnceLock::new()).collect();
+        Self { sampleRate, channels, spectra }
+    }
+
+    pub fn length(&self) -> usize {
+        self.channels.first().map_or(0, Vec::len)
+    }
+
+    // Complex response of one of the IR's channels at each frequency, mono IRs serve every channel.
+    // Read off the channel's spectrum, magnitude and phase interpolated between its bins.
+    pub fn frequencyResponse(&self, channel: usize, frequencies: &[f64]) -> Vec<(f64, f64)> {
+        let channel = channel.min(self.channels.len() - 1);
+        let spectrum = self.spectra[channel].get_or_init(|| spectrum(&self.channels[channel]));
+        let last = spectrum.len() - 1;
+        let binWidth = self.sampleRate as f64 / (last * 2) as f64;
+
+        frequencies
+            .iter()
+            .map(|&frequency| {
+                let position = (frequency / binWidth).clamp(0., last as f64);
+                let bin = (position as usize).min(last - 1);
+                let t = position - bin as f64;
+                let ((re0, im0), (re1, im1)) = (spectrum[bin], spectrum[bin + 1]);
+
+                let (magnitude0, magnitude1) = ((re0 as f64).hypot(im0 as f64), (re1 as f64).hypot(im1 as f64));
+                let (phase0, phase1) = ((im0 as f64).atan2(re0 as f64), (im1 as f64).atan2(re1 as f64));
+                // the padding keeps neighbouring bins less than half a turn apart
+                let phaseStep = (phase1 - phase0 + PI).rem_euclid(2. * PI) - PI;
+
+                let (sin, cos) = (phase0 + t * phaseStep).sin_cos();
+                let magnitude = magnitude0 + t * (magnitude1 - magnitude0);
+                (magnitude * cos, magnitude * sin)
+            })
+            .collect()
+    }
+}
+
+// In place radix-2 FFT, the length has to be a power of two
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let size = re.len();
+
+    let mut j = 0;
+    for i in 1..size {
+        let mut bit = size >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= size {
+        let (sinStep, cosStep) = (-2. * PI / length as f64).sin_cos();
+        for start in (0..size).step_by(length) {
+            let (mut cos, mut sin) = (1f64, 0f64);
+            for k in 0..length / 2 {
+                let (a, b) = (start + k, start + k + length / 2);
+                let (bRe, bIm) = (re[b] * cos - im[b] * sin, re[b] * sin + im[b] * cos);
+
+                (re[b], im[b]) = (re[a] - bRe, im[a] - bIm);
+                (re[a], im[a]) = (re[a] + bRe, im[a] + bIm);
+                (cos, sin) = (cos * cosStep - sin * sinStep, sin * cosStep + cos * sinStep);
+            }
+        }
+        length <<= 1;
+    }
+}
+
+// Bins from 0 Hz to Nyquist, zero padded to twice the length or more
+fn spectrum(samples: &[f32]) -> Vec<(f32, f32)> {
+    let size = (samples.len() * 2).next_power_of_two().max(MIN_SPECTRUM_SIZE);
+    let mut re: Vec<f64> = samples.iter().map(|&s| s as f64).collect();
+    re.resize(size, 0.);
+    let mut im = vec![0.; size];
+
+    fft(&mut re, &mut im);
+    (0..=size / 2).map(|k| (re[k] as f32, im[k] as f32)).collect()
+}
+
+// most recently loaded first
+static CACHE: Mutex<Vec<(PathBuf, SystemTime, Arc<ImpulseResponse>)>> = Mutex::new(Vec::new());
+
+fn u16At(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn u32At(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+// Reads PCM (16, 24 and 32 bit) and 32 bit float WAVs, including WAVE_FORMAT_EXTENSIBLE ones
+pub fn parseWav(bytes: &[u8]) -> Result<ImpulseResponse, String> {
+    if bytes.get(0..4) != Some(b"RIFF") || bytes.get(8..12) != Some(b"WAVE") {
+        return Err("not a WAV file".into());
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut offset = 12;
+    while let (Some(id), Some(size)) = (bytes.get(offset..offset + 4), u32At(bytes, offset + 4)) {
+        let start = offset + 8;
+        let end = (start + size as usize).min(bytes.len());
+
+        match id {
+            b"fmt " => format = Some(&bytes[start..end]),
+            b"data" => data = Some(&bytes[start..end]),
+            _ => {},
+        }
+
+        // chunks are padded to an even size
+        offset = start + size as usize + (size as usize & 1);
+    }
+
+    let format = format.ok_or("the WAV has no format chunk")?;
+    let data = data.ok_or("the WAV has no data chunk")?;
+    let invalid = || "the WAV's format chunk is invalid".to_string();
+
+    let mut formatTag = u16At(format, 0).ok_or_else(invalid)?;
+    let channelCount = u16At(format, 2).ok_or_else(invalid)?;
+    let sampleRate = u32At(format, 4).ok_or_else(invalid)?;
+    let bitsPerSample = u16At(format, 14).ok_or_else(invalid)?;
+    if formatTag == 0xFFFE {
+        // the sub format GUID starts with the actual format tag
+        formatTag = u16At(format, 24).ok_or_else(invalid)?;
+    }
+    if channelCount == 0 {
+        return Err("the WAV has no channels".into());
+    }
+
+    let decode: fn(&[u8]) -> f32 = match (formatTag, bitsPerSample) {
+        (1, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32_768.,
+        (1, 24) => |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.,
+        (1, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.,
+        (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        _ => return Err(format!("WAV sample format {} with {} bits is not supported", formatTag, bitsPerSample)),
+    };
+
+    let frameSize = (bitsPerSample / 8) as usize * channelCount as usize;
+    let mut channels = vec![Vec::with_capacity(data.len() / frameSize); channelCount as usize];
+    for frame in data.chunks_exact(frameSize) {
+        for (channel, sample) in frame.chunks_exact((bitsPerSample / 8) as usize).enumerate() {
+            channels[channel].push(decode(sample));
+        }
+    }
+
+    Ok(ImpulseResponse::new(sampleRate, channels))
+}
+
+// Loads an impulse response and checks it's one APO can use, the last few loaded are cached until their files change
+pub fn load(path: &Path) -> Result<Arc<ImpulseResponse>, String> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    if let Some((_, _, ir)) = CACHE.lock().unwrap().iter().find(|(cachedPath, cachedModified, _)| cachedPath == path && *cachedModified == modified) {
+        return Ok(ir.clone());
+    }
+
+    let ir = parseWav(&fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?)?;
+
+    if !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&ir.sampleRate) {
+        return Err(format!("a sample rate of {} Hz is not supported", ir.sampleRate));
+    }
+    // each channel has to belong to a speaker of one of the layouts
+    if ir.channels.len() > 1 && ChannelLayout::fromChannelCount(ir.channels.len()).is_none() {
+        return Err(format!("impulse responses with {} channels are not supported, use a mono, stereo, 5.1 or 7.1 file", ir.channels.len()));
+    }
+    if ir.length() == 0 {
+        return Err("the impulse response is empty".into());
+    }
+    if ir.length() as f64 / ir.sampleRate as f64 > MAX_SECONDS {
+        return Err(format!("the impulse response is longer than {} seconds", MAX_SECONDS));
+    }
+
+    let ir = Arc::new(ir);
+    let mut cache = CACHE.lock().unwrap();
+    cache.retain(|(cachedPath, _, _)| cachedPath != path);
+    cache.insert(0, (path.to_path_buf(), modified, ir.clone()));
+    cache.truncate(MAX_CACHED);
+
+    Ok(ir)
+}
+
+// `deviceRate` is the output device's rate, an IR recorded at another one gets resampled
+pub fn info(path: &Path, deviceRate: u32) -> Result<ImpulseResponseInfo, String> {
+    let ir = load(path)?;
+    let mut warnings = vec![];
+
+    if ir.sampleRate != deviceRate {
+        warnings.push(format!("recorded at {} Hz, it will be resampled to the device's {} Hz", ir.sampleRate, deviceRate));
+    }
+    let peak = ir.channels.iter().flatten().fold(0f32, |peak, s| peak.max(s.abs()));
+    if peak > 1. {
+        warnings.push("the impulse response clips".into());
+    }
+
+    Ok(ImpulseResponseInfo {
+        path: path.to_string_lossy().into(),
+        sampleRate: ir.sampleRate,
+        channels: ir.channels.len() as u16,
+        length: ir.length() as u32,
+        duration: ir.length() as f32 / ir.sampleRate as f32,
+        warnings,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dft(samples: &[f32], frequency: f64, sampleRate: f64) -> (f64, f64) {
+        samples.iter().enumerate().fold((0., 0.), |(re, im), (n, &sample)| {
+            let (sin, cos) = (-2. * PI * frequency * n as f64 / sampleRate).sin_cos();
+            (re + sample as f64 * cos, im + sample as f64 * sin)
+        })
+    }
+
+    // a direct sound, an early reflection and a decaying resonance, like a short room IR
+    fn roomIr(sampleRate: f64) -> Vec<f32> {
+        (0..4800)
+            .map(|n| {
+                let t = n as f64 / sampleRate;
+                let direct = if n == 24 { 1. } else { 0. };
+                let reflection = if n == 300 { -0.4 } else { 0. };
+                (direct + reflection + 0.3 * (-t * 60.).exp() * (2. * PI * 180. * t).sin()) as f32
+            })
+            .collect()
+    }
+
+    fn wav(samples: &[i16], sampleRate: u32) -> Vec<u8> {
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend((36 + samples.len() as u32 * 2).to_le_bytes());
+        bytes.extend(b"WAVEfmt ");
+        bytes.extend(16u32.to_le_bytes());
+        for field in [1u16, 1] {
+            bytes.extend(field.to_le_bytes());
+        }
+        bytes.extend(sampleRate.to_le_bytes());
+        bytes.extend((sampleRate * 2).to_le_bytes());
+        bytes.extend(2u16.to_le_bytes());
+        bytes.extend(16u16.to_le_bytes());
+        bytes.extend(b"data");
+        bytes.extend((samples.len() as u32 * 2).to_le_bytes());
+        bytes.extend(samples.iter().flat_map(|s| s.to_le_bytes()));
+
+        bytes
+    }
+
+    #[test]
+    fn fftMatchesTheDft() {
+        let samples: Vec<f32> = (0..64).map(|n| ((n * 37 % 11) as f32 - 5.) / 5.).collect();
+        let (mut re, mut im): (Vec<f64>, Vec<f64>) = (samples.iter().map(|&s| s as f64).collect(), vec![0.; 64]);
+        fft(&mut re, &mut im);
+
+        for k in 0..64 {
+            let (expectedRe, expectedIm) = dft(&samples, k as f64, 64.);
+            assert!((re[k] - expectedRe).abs() < 1e-9 && (im[k] - expectedIm).abs() < 1e-9, "bin {}", k);
+        }
+    }
+
+    #[test]
+    fn responseMatchesTheDft() {
+        let sampleRate = 48_000.;
+        let ir = ImpulseResponse::new(sampleRate as u32, vec![roomIr(sampleRate)]);
+        let frequencies: Vec<f64> = (0..300).map(|i| 20. * 1000f64.powf(i as f64 / 299.)).collect();
+
+        for (&frequency, (re, im)) in frequencies.iter().zip(ir.frequencyResponse(0, &frequencies)) {
+            let (expectedRe, expectedIm) = dft(&ir.channels[0], frequency, sampleRate);
+            let (magnitude, expected) = (re.hypot(im), expectedRe.hypot(expectedIm));
+            let phaseError = ((im.atan2(re) - expectedIm.atan2(expectedRe) + PI).rem_euclid(2. * PI) - PI).abs();
+
+            assert!((20. * (magnitude / expected).log10()).abs() < 0.1, "{} Hz: {} instead of {}", frequency, magnitude, expected);
+            assert!(phaseError < 0.05, "{} Hz: phase off by {}", frequency, phaseError);
+        }
+    }
+
+    #[test]
+    fn cachesEachFileUntilItChanges() {
+        let dir = std::env::temp_dir().join(format!("slyshmefx-irs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (left, right) = (dir.join("left.wav"), dir.join("right.wav"));
+        fs::write(&left, wav(&[16_384, 0, 0, 0], 48_000)).unwrap();
+        fs::write(&right, wav(&[0, 16_384, 0, 0], 48_000)).unwrap();
+
+        // alternating between two channels' IRs doesn't reload them
+        let (first, second) = (load(&left).unwrap(), load(&right).unwrap());
+        assert!(Arc::ptr_eq(&first, &load(&left).unwrap()));
+        assert!(Arc::ptr_eq(&second, &load(&right).unwrap()));
+        assert_eq!(first.channels[0][0], 0.5);
+
+        // a different modification time is a different file
+        fs::write(&left, wav(&[8_192, 0, 0, 0], 48_000)).unwrap();
+        let file = fs::File::options().write(true).open(&left).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(10)).unwrap();
+        assert_eq!(load(&left).unwrap().channels[0][0], 0.25);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn warnsAboutOtherRatesThanTheDevices() {
+        let dir = std::env::temp_dir().join(format!("slyshmefx-ir-rates-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ir.wav");
+        fs::write(&path, wav(&[16_384, 0, 0, 0], 44_100)).unwrap();
+
+        assert!(info(&path, 44_100).unwrap().warnings.is_empty());
+        assert_eq!(info(&path, 96_000).unwrap().warnings, vec!["recorded at 44100 Hz, it will be resampled to the device's 96000 Hz"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{apo::bandwidthToQ, response::{graphicEqGain, logFrequencies, Biquad}, structs::{EqualiserChannelSettings, FitLimits, Filter, FilterType, FilterWidth, GraphicEqPoint}};
+
+
+
+const GRID_POINTS: usize = 96;
+const REFINE_PASSES: usize = 40;
+// targets without a graphic EQ get the curve approximated with this many peaking filters instead
+const GRAPHIC_EQ_BANDS: usize = 16;
+
+struct Fit<'a> {
+    frequencies: Vec<f64>,
+    target: Vec<f64>,
+    filters: Vec<Filter>,
+    // each filter's dB response on the grid, so a change only recomputes one of them
+    contributions: Vec<Vec<f64>>,
+    limits: &'a FitLimits,
+    sampleRate: f64,
+}
+impl Fit<'_> {
+    fn contribution(&self, filter: &Filter) -> Vec<f64> {
+        let biquad = Biquad::fromFilter(filter, self.sampleRate);
+        self.frequencies.iter().map(|&f| biquad.magnitudeDb(f, self.sampleRate)).collect()
+    }
+
+    fn residual(&self) -> Vec<f64> {
+        (0..self.frequencies.len())
+            .map(|i| self.target[i] - self.contributions.iter().map(|c| c[i]).sum::<f64>())
+            .collect()
+    }
+
+    fn error(&self) -> f64 {
+        let residual = self.residual();
+        residual.iter().map(|r| r * r).sum::<f64>() / residual.len() as f64
+    }
+
+    fn clamped(&self, mut filter: Filter) -> Filter {
+        filter.frequency = filter.frequency.clamp(self.limits.minFrequency, self.limits.maxFrequency);
+        filter.gain = filter.gain.clamp(-self.limits.maxGain, self.limits.maxGain);
+        if let FilterWidth::Q(q) = filter.width {
+            filter.width = FilterWidth::Q(q.clamp(self.limits.minQ, self.limits.maxQ));
+        }
+
+        filter
+    }
+
+    // Places a peak on the largest remaining deviation, as wide as the deviation is at half its height
+    fn addPeak(&mut self) {
+        let residual = self.residual();
+        let smoothed: Vec<f64> = (0..residual.len())
+            .map(|i| {
+                let window = &residual[i.saturating_sub(1)..(i + 2).min(residual.len())];
+                window.iter().sum::<f64>() / window.len() as f64
+            })
+            .collect();
+
+        let (centre, &peak) = smoothed.iter().enumerate().max_by(|a, b| a.1.abs().total_cmp(&b.1.abs())).unwrap();
+        let inside = |i: usize| smoothed[i] * peak.signum() > peak.abs() / 2.;
+        let (mut low, mut high) = (centre, centre);
+        while low > 0 && inside(low - 1) {
+            low -= 1;
+        }
+        while high + 1 < smoothed.len() && inside(high + 1) {
+            high += 1;
+        }
+
+        let octaves = (self.frequencies[high] / self.frequencies[low]).log2().max(0.1);
+        let filter = self.clamped(Filter {
+            filterType: FilterType::Peak,
+            frequency: self.frequencies[centre] as f32,
+            gain: peak as f32,
+            width: FilterWidth::Q(bandwidthToQ(octaves as f32)),
+            enabled: true,
+        });
+
+        self.contributions.push(self.contribution(&filter));
+        self.filters.push(filter);
+    }
+
+    // Coordinate descent over every filter's frequency, gain and Q, halving the steps whenever a pass finds nothing better
+    fn refine(&mut self) {
+        let (mut octaveStep, mut gainStep, mut qStep) = (1f32 / 3., 1f32, 1.25f32);
+        let mut error = self.error();
+
+        for _ in 0..REFINE_PASSES {
+            let mut improved = false;
+
+            for i in 0..self.filters.len() {
+                let filter = self.filters[i];
+                let q = match filter.width {
+                    FilterWidth::Q(q) => q,
+                    FilterWidth::Slope(_) => continue,
+                };
+                let candidates = [
+                    Filter { frequency: filter.frequency * octaveStep.exp2(), ..filter },
+                    Filter { frequency: filter.frequency / octaveStep.exp2(), ..filter },
+                    Filter { gain: filter.gain + gainStep, ..filter },
+                    Filter { gain: filter.gain - gainStep, ..filter },
+                    Filter { width: FilterWidth::Q(q * qStep), ..filter },
+                    Filter { width: FilterWidth::Q(q / qStep), ..filter },
+                ];
+
+                for candidate in candidates {
+                    let candidate = self.clamped(candidate);
+                    let contribution = self.contribution(&candidate);
+                    let previous = std::mem::replace(&mut self.contributions[i], contribution);
+                    let candidateError = self.error();
+
+                    if candidateError < error - 1e-9 {
+                        error = candidateError;
+                        self.filters[i] = candidate;
+                        improved = true;
+                    } else {
+                        self.contributions[i] = previous;
+                    }
+                }
+            }
+
+            if !improved {
+                octaveStep /= 2.;
+                gainStep /= 2.;
+                qStep = qStep.sqrt();
+            }
+        }
+    }
+}
+
+pub struct FilterFit {
+    pub filters: Vec<Filter>, // sorted by frequency
+    pub level: f32, // dB the curve sits at, for the preamp to make up
+    pub error: f64, // RMS deviation from the curve in dB, its level aside
+}
+
+// Fits up to `bands` peaking filters to a target curve. Only its shape is fitted, the overall level is left to the preamp.
+pub fn fitFilters(target: &[GraphicEqPoint], bands: usize, limits: &FitLimits, sampleRate: f64) -> FilterFit {
+    let limits = FitLimits {
+        maxFrequency: limits.maxFrequency.min((sampleRate / 2. * 0.95) as f32),
+        ..*limits
+    };
+    let frequencies: Vec<f64> = logFrequencies(GRID_POINTS, sampleRate)
+        .into_iter()
+        .filter(|&f| f >= limits.minFrequency as f64 && f <= limits.maxFrequency as f64)
+        .collect();
+    if frequencies.is_empty() || target.is_empty() {
+        return FilterFit { filters: vec![], level: 0., error: 0. };
+    }
+
+    let mut target: Vec<f64> = frequencies.iter().map(|&f| graphicEqGain(target, f)).collect();
+    // the median is the level most of the curve sits at, a mean would be dragged around by the boosts
+    let mut sorted = target.clone();
+    sorted.sort_by(f64::total_cmp);
+    let level = sorted[sorted.len() / 2];
+    target.iter_mut().for_each(|t| *t -= level);
+
+    let mut fit = Fit {
+        frequencies,
+        target,
+        filters: vec![],
+        contributions: vec![],
+        limits: &limits,
+        sampleRate,
+    };
+
+    for _ in 0..bands {
+        fit.addPeak();
+    }
+    fit.refine();
+
+    let error = fit.error().sqrt();
+    let mut filters: Vec<Filter> = fit.filters.into_iter().filter(|f| f.gain.abs() >= 0.1).collect();
+    filters.sort_by(|a, b| a.frequency.total_cmp(&b.frequency));
+
+    FilterFit { filters, level: level as f32, error }
+}
+
+// A channel's filters with its graphic EQ, if any, converted to peaking filters running at `sampleRate`,
+// along with the level the graphic EQ sits at which has to be added to the preamp
+pub fn parametricFilters(channel: &EqualiserChannelSettings, sampleRate: f64) -> (Vec<Filter>, f32) {
+    let mut filters = channel.filters.clone();
+    if channel.graphicEq.is_empty() {
+        return (filters, 0.);
+    }
+
+    let fit = fitFilters(&channel.graphicEq, GRAPHIC_EQ_BANDS, &FitLimits::default(), sampleRate);
+    filters.extend(fit.filters);
+
+    (filters, fit.level)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn autoEqCurve() -> Vec<GraphicEqPoint> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/autoeq");
+        let import = crate::apo::parseConfig(&std::fs::read_to_string(dir.join("GraphicEQ.txt")).unwrap(), &dir);
+        assert!(import.warnings.is_empty(), "{:?}", import.warnings);
+
+        import.settings.channels[0].graphicEq.clone()
+    }
+
+    fn assertWithin(filters: &[Filter], limits: &FitLimits) {
+        for filter in filters {
+            assert_eq!(filter.filterType, FilterType::Peak);
+            assert!(filter.gain.abs() <= limits.maxGain, "{:?}", filter);
+            assert!((limits.minFrequency..=limits.maxFrequency).contains(&filter.frequency), "{:?}", filter);
+            match filter.width {
+                FilterWidth::Q(q) => assert!((limits.minQ..=limits.maxQ).contains(&q), "{:?}", filter),
+                FilterWidth::Slope(_) => panic!("peaks have a Q"),
+            }
+        }
+        assert!(filters.windows(2).all(|pair| pair[0].frequency <= pair[1].frequency), "sorted by frequency");
+    }
+
+    #[test]
+    fn fitsAutoEqProfile() {
+        let curve = autoEqCurve();
+        assert_eq!(curve.len(), 123);
+
+        let limits = FitLimits::default();
+        let FilterFit { filters, error, .. } = fitFilters(&curve, 10, &limits, 48_000.);
+        assert!(!filters.is_empty() && filters.len() <= 10);
+        assert!(error < 0.3, "{} dB RMS", error);
+        assertWithin(&filters, &limits);
+
+        // more bands never fit worse
+        let fewer = fitFilters(&curve, 4, &limits, 48_000.).error;
+        assert!(fewer >= error, "{} dB with 4 bands, {} dB with 10", fewer, error);
+    }
+
+    #[test]
+    fn staysWithinTightLimits() {
+        let limits = FitLimits { minFrequency: 50., maxFrequency: 10_000., maxGain: 2., minQ: 0.7, maxQ: 2. };
+        let FilterFit { filters, error, .. } = fitFilters(&autoEqCurve(), 10, &limits, 48_000.);
+
+        assertWithin(&filters, &limits);
+        assert!(error.is_finite());
+    }
+
+    // the curve's level goes to the preamp, a flat curve at any level needs no filters
+    #[test]
+    fn levelIsLeftToThePreamp() {
+        let flat = [GraphicEqPoint { frequency: 20., gain: -6. }, GraphicEqPoint { frequency: 20_000., gain: -6. }];
+        let fit = fitFilters(&flat, 5, &FitLimits::default(), 48_000.);
+
+        assert!(fit.filters.is_empty(), "{:?}", fit.filters);
+        assert!(fit.error < 1e-6);
+        assert_eq!(fit.level, -6.);
+
+        let channel = EqualiserChannelSettings { graphicEq: flat.to_vec(), filters: vec![], ..EqualiserChannelSettings::new(crate::structs::Speaker::FrontLeft) };
+        assert_eq!(parametricFilters(&channel, 48_000.), (vec![], -6.));
+    }
+}
@@ -0,0 +1,625 @@
+use serde_json::{json, Map, Value};
+use serde_yaml::{Mapping, Value as Yaml};
+
+use crate::{convolution, fitting::parametricFilters, response::slopeToQ, structs::{ApoImport, ChannelLayout, Compressor, DynamicsSettings, EqualiserChannelSettings, EqualiserExport, EqualiserSettings, Filter, FilterType, FilterWidth, Limiter, Speaker}};
+
+
+
+// EasyEffects only knows a single equaliser instance per preset that we care about
+const EASYEFFECTS_PLUGIN: &str = "equalizer#0";
+const CAMILLA_PREFIX: &str = "slyshmefx";
+
+fn emptyChannel(speaker: Speaker) -> EqualiserChannelSettings {
+    EqualiserChannelSettings { filters: vec![], ..EqualiserChannelSettings::new(speaker) }
+}
+
+fn qOf(filter: &Filter) -> f32 {
+    match filter.width {
+        FilterWidth::Q(q) => q,
+        FilterWidth::Slope(slope) => slopeToQ(slope, filter.gain),
+    }
+}
+
+fn finish(layout: ChannelLayout, channels: Vec<EqualiserChannelSettings>, warnings: Vec<String>) -> ApoImport {
+    ApoImport {
+        settings: EqualiserSettings::fromChannels(layout, channels),
+        warnings,
+    }
+}
+
+// EasyEffects output preset with just the equaliser, graphic EQ curves are converted to peaking filters at `sampleRate`.
+// EasyEffects' equaliser is stereo, only the front pair is exported, and everything else the preset would need
+// more plugins for is left out with a warning.
+pub fn toEasyEffects(settings: &EqualiserSettings, sampleRate: f64) -> EqualiserExport {
+    let settings = &settings.compensated(sampleRate);
+    let (leftChannel, rightChannel) = (&settings.channels[0], &settings.channels[1]);
+    let ((leftFilters, leftLevel), (rightFilters, rightLevel)) = (parametricFilters(leftChannel, sampleRate), parametricFilters(rightChannel, sampleRate));
+
+    let bands = |filters: &[Filter]| -> Map<String, Value> {
+        filters
+            .iter()
+            .enumerate()
+            .map(|(i, filter)| {
+                let filterType = match filter.filterType {
+                    FilterType::Peak => "Bell",
+                    FilterType::LowShelf => "Lo-shelf",
+                    FilterType::HighShelf => "Hi-shelf",
+                    FilterType::LowPass => "Lo-pass",
+                    FilterType::HighPass => "Hi-pass",
+                    FilterType::Notch => "Notch",
+                    FilterType::BandPass => "Resonance",
+                    FilterType::AllPass => "Allpass",
+                };
+
+                (
+                    format!("band{}", i),
+                    json!({
+                        "frequency": filter.frequency,
+                        "gain": filter.gain,
+                        "mode": "RLC (BT)",
+                        "mute": !filter.enabled,
+                        "q": qOf(filter),
+                        "slope": "x1",
+                        "solo": false,
+                        "type": filterType,
+                        "width": 4.0,
+                    }),
+                )
+            })
+            .collect()
+    };
+
+    let split = !(settings.linked || leftChannel.sameAs(rightChannel));
+    let (left, right, leftPreamp, rightPreamp) = match split {
+        true => (bands(&leftFilters), bands(&rightFilters), leftChannel.preamp + leftLevel, rightChannel.preamp + rightLevel),
+        false => (bands(&leftFilters), bands(&leftFilters), leftChannel.preamp + leftLevel, leftChannel.preamp + leftLevel),
+    };
+    let mut warnings = vec![];
+
+    // a split equaliser still has one input gain, the quieter preamp is used so neither side gets louder
+    let preamp = leftPreamp.min(rightPreamp);
+    if split && leftPreamp != rightPreamp {
+        let (louder, by) = match leftPreamp > rightPreamp {
+            true => ("left", leftPreamp - preamp),
+            false => ("right", rightPreamp - preamp),
+        };
+        warnings.push(format!("EasyEffects has one input gain for both channels, {} dB is used and the {} channel is {} dB quieter than set", preamp, louder, by));
+    }
+
+    let others = settings.channels.len() - 2;
+    if others > 0 {
+        warnings.push(format!("EasyEffects' equaliser is stereo, the other {} channel(s) are left out", others));
+    }
+    let front = [leftChannel, rightChannel];
+    if front.iter().any(|c| c.convolution.is_some()) {
+        warnings.push("impulse responses are left out, load them into EasyEffects' convolver".into());
+    }
+    if front.iter().any(|c| c.trim != 0.) {
+        warnings.push("channel trims are left out".into());
+    }
+    if front.iter().any(|c| c.delay > 0.) {
+        warnings.push("channel delays are left out".into());
+    }
+    if !settings.spatial.isNeutral() {
+        warnings.push("crossfeed, balance, width, mono and channel swap are left out".into());
+    }
+    if settings.dynamics.compressor.enabled || settings.dynamics.limiter.enabled {
+        warnings.push("the compressor and limiter are left out, EasyEffects has plugins of its own for them".into());
+    }
+
+    let preset = json!({
+        "output": {
+            "blocklist": [],
+            EASYEFFECTS_PLUGIN: {
+                "balance": 0.0,
+                "bypass": false,
+                "input-gain": preamp,
+                "left": left,
+                "mode": "IIR",
+                "num-bands": left.len().max(right.len()),
+                "output-gain": 0.0,
+                "pitch-left": 0.0,
+                "pitch-right": 0.0,
+                "right": right,
+                "split-channels": split,
+            },
+            "plugins_order": [EASYEFFECTS_PLUGIN],
+        }
+    });
+
+    EqualiserExport {
+        contents: serde_json::to_string_pretty(&preset).unwrap(),
+        warnings,
+    }
+}
+
+pub fn fromEasyEffects(contents: &str) -> Result<ApoImport, String> {
+    let preset: Value = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+    let output = preset.get("output").and_then(Value::as_object).ok_or("not an EasyEffects output preset")?;
+    // older versions name the plugin without an instance number
+    let equaliser = output
+        .iter()
+        .find(|(name, _)| name.starts_with("equalizer"))
+        .map(|(_, plugin)| plugin)
+        .ok_or("the preset has no equalizer")?;
+
+    let mut warnings = vec![];
+    let number = |value: &Value, key: &str| value.get(key).and_then(Value::as_f64).map(|n| n as f32);
+    let inputGain = number(equaliser, "input-gain").unwrap_or(0.) + number(equaliser, "output-gain").unwrap_or(0.);
+    let bandCount = equaliser.get("num-bands").and_then(Value::as_u64).unwrap_or(u64::MAX) as usize;
+
+    let channel = |side: &str, speaker: Speaker, warnings: &mut Vec<String>| {
+        let mut settings = emptyChannel(speaker);
+        settings.preamp = inputGain;
+
+        let Some(bands) = equaliser.get(side) else {
+            return settings;
+        };
+        for i in 0..bandCount {
+            let Some(band) = bands.get(format!("band{}", i)) else {
+                break;
+            };
+
+            let filterType = match band.get("type").and_then(Value::as_str).unwrap_or("Off") {
+                "Bell" => FilterType::Peak,
+                "Lo-shelf" => FilterType::LowShelf,
+                "Hi-shelf" => FilterType::HighShelf,
+                "Lo-pass" => FilterType::LowPass,
+                "Hi-pass" => FilterType::HighPass,
+                "Notch" => FilterType::Notch,
+                "Resonance" | "Bandpass" => FilterType::BandPass,
+                "Allpass" => FilterType::AllPass,
+                "Off" => continue,
+                other => {
+                    warnings.push(format!("{} band {}: type {} is not supported, ignored", side, i, other));
+                    continue;
+                },
+            };
+            if band.get("slope").and_then(Value::as_str).is_some_and(|slope| slope != "x1") {
+                warnings.push(format!("{} band {}: steeper slopes are not supported, imported as a single filter", side, i));
+            }
+
+            settings.filters.push(Filter {
+                filterType,
+                frequency: number(band, "frequency").unwrap_or(1000.),
+                gain: number(band, "gain").unwrap_or(0.),
+                width: FilterWidth::Q(number(band, "q").unwrap_or(0.707)),
+                enabled: !band.get("mute").and_then(Value::as_bool).unwrap_or(false),
+            });
+        }
+
+        settings
+    };
+
+    let left = channel("left", Speaker::FrontLeft, &mut warnings);
+    let right = if equaliser.get("split-channels").and_then(Value::as_bool).unwrap_or(false) {
+        channel("right", Speaker::FrontRight, &mut warnings)
+    } else {
+        emptyChannel(Speaker::FrontRight).linkedTo(&left)
+    };
+
+    Ok(finish(ChannelLayout::Stereo, vec![left, right], warnings))
+}
+
+// CamillaDSP filters and pipeline steps (v3 syntax) for each of the layout's channels, numbered in its order,
+// to be merged into a config with its devices, graphic EQ curves are fitted at `sampleRate`
+pub fn toCamillaDsp(settings: &EqualiserSettings, sampleRate: f64) -> EqualiserExport {
+    let settings = &settings.compensated(sampleRate);
+    let mut filters = Mapping::new();
+    let mut pipeline = vec![];
+
+    for (index, channel) in settings.channels.iter().enumerate() {
+        let side = channel.speaker.short().to_ascii_lowercase();
+        let mut names = vec![];
+
+        let mut add = |name: String, definition: Yaml| {
+            filters.insert(Yaml::from(name.clone()), definition);
+            names.push(Yaml::from(name));
+        };
+
+        let (channelFilters, level) = parametricFilters(channel, sampleRate);
+        add(
+            format!("{}_{}_preamp", CAMILLA_PREFIX, side),
+            serde_yaml::to_value(json!({ "type": "Gain", "parameters": { "gain": channel.preamp + level, "scale": "dB" } })).unwrap(),
+        );
+
+        for (i, filter) in channelFilters.iter().filter(|f| f.enabled).enumerate() {
+            let (biquadType, gain) = match filter.filterType {
+                FilterType::Peak => ("Peaking", true),
+                FilterType::LowShelf => ("Lowshelf", true),
+                FilterType::HighShelf => ("Highshelf", true),
+                FilterType::LowPass => ("Lowpass", false),
+                FilterType::HighPass => ("Highpass", false),
+                FilterType::Notch => ("Notch", false),
+                FilterType::BandPass => ("Bandpass", false),
+                FilterType::AllPass => ("Allpass", false),
+            };
+
+            let mut parameters = json!({ "type": biquadType, "freq": filter.frequency });
+            if gain {
+                parameters["gain"] = json!(filter.gain);
+            }
+            match filter.width {
+                // CamillaDSP's shelf slope is in dB per octave as well
+                FilterWidth::Slope(slope) => parameters["slope"] = json!(slope),
+                FilterWidth::Q(q) => parameters["q"] = json!(q),
+            }
+
+            add(
+                format!("{}_{}_{}", CAMILLA_PREFIX, side, i + 1),
+                serde_yaml::to_value(json!({ "type": "Biquad", "parameters": parameters })).unwrap(),
+            );
+        }
+
+        if let Some(path) = &channel.convolution {
+            let irChannel = convolution::load(std::path::Path::new(path)).map_or(0, |ir| channel.speaker.irChannel(ir.channels.len()));
+            add(
+                format!("{}_{}_convolution", CAMILLA_PREFIX, side),
+                serde_yaml::to_value(json!({ "type": "Conv", "parameters": { "type": "Wav", "filename": path, "channel": irChannel } })).unwrap(),
+            );
+        }
+
+        if channel.trim != 0. {
+            add(
+                format!("{}_{}_trim", CAMILLA_PREFIX, side),
+                serde_yaml::to_value(json!({ "type": "Gain", "parameters": { "gain": channel.trim, "scale": "dB" } })).unwrap(),
+            );
+        }
+        if channel.delay > 0. {
+            add(
+                format!("{}_{}_delay", CAMILLA_PREFIX, side),
+                serde_yaml::to_value(json!({ "type": "Delay", "parameters": { "delay": channel.delay, "unit": "ms" } })).unwrap(),
+            );
+        }
+
+        let mut step = Mapping::new();
+        step.insert("type".into(), "Filter".into());
+        step.insert("channels".into(), Yaml::Sequence(vec![Yaml::from(index as u64)]));
+        step.insert("names".into(), Yaml::Sequence(names));
+        pipeline.push(Yaml::Mapping(step));
+    }
+
+    // CamillaDSP has no compressor knee, it's left out. The routing would need a mixer in front of the channels'
+    // filters, which the config it's merged into has to define for its own devices.
+    let mut warnings = vec![];
+    if !settings.spatial.isNeutral() {
+        warnings.push("crossfeed, balance, width, mono and channel swap are left out, CamillaDSP needs a mixer for them".into());
+    }
+    let allChannels: Vec<usize> = (0..settings.channels.len()).collect();
+    let mut processors = Mapping::new();
+    let compressor = &settings.dynamics.compressor;
+    if compressor.enabled {
+        if compressor.knee > 0. {
+            warnings.push(format!("CamillaDSP's compressor has a hard knee, the {} dB knee is left out", compressor.knee));
+        }
+        let name = format!("{}_compressor", CAMILLA_PREFIX);
+        processors.insert(
+            Yaml::from(name.clone()),
+            serde_yaml::to_value(json!({ "type": "Compressor", "parameters": {
+                "channels": allChannels.len(),
+                "monitor_channels": allChannels,
+                "process_channels": allChannels,
+                "attack": compressor.attack as f64 / 1000.,
+                "release": compressor.release as f64 / 1000.,
+                "threshold": compressor.threshold,
+                "factor": compressor.ratio,
+                "makeup_gain": compressor.makeupGain,
+            } }))
+            .unwrap(),
+        );
+        pipeline.push(serde_yaml::to_value(json!({ "type": "Processor", "name": name })).unwrap());
+    }
+    let limiter = &settings.dynamics.limiter;
+    if limiter.enabled {
+        let name = format!("{}_limiter", CAMILLA_PREFIX);
+        filters.insert(
+            Yaml::from(name.clone()),
+            serde_yaml::to_value(json!({ "type": "Limiter", "parameters": { "clip_limit": limiter.threshold } })).unwrap(),
+        );
+        pipeline.push(serde_yaml::to_value(json!({ "type": "Filter", "channels": allChannels, "names": [name] })).unwrap());
+    }
+
+    let mut config = Mapping::new();
+    config.insert("filters".into(), Yaml::Mapping(filters));
+    if !processors.is_empty() {
+        config.insert("processors".into(), Yaml::Mapping(processors));
+    }
+    config.insert("pipeline".into(), Yaml::Sequence(pipeline));
+
+    EqualiserExport { contents: serde_yaml::to_string(&config).unwrap(), warnings }
+}
+
+// Reads the pipeline's filter steps, v2's `channel` and v3's `channels` both work.
+// The layout is the smallest one with as many channels as the steps name, gains, trims included, end up in the preamp.
+pub fn fromCamillaDsp(contents: &str) -> Result<ApoImport, String> {
+    let config: Yaml = serde_yaml::from_str(contents).map_err(|e| e.to_string())?;
+    let filters = config.get("filters").ok_or("the config has no filters")?;
+    let pipeline = config.get("pipeline").and_then(Yaml::as_sequence).ok_or("the config has no pipeline")?;
+
+    let mut warnings = vec![];
+    let mut dynamics = DynamicsSettings::default();
+    // numbered like a 7.1 device until the layout is known
+    let mut channels: Vec<EqualiserChannelSettings> = ChannelLayout::Surround71.speakers().iter().map(|&s| emptyChannel(s)).collect();
+    let mut highest = 1;
+    let number = |value: &Yaml, key: &str| value.get(key).and_then(Yaml::as_f64).map(|n| n as f32);
+
+    for step in pipeline {
+        match step.get("type").and_then(Yaml::as_str) {
+            Some("Filter") => {},
+            // processors apply to the whole pipeline's output, dynamics are global anyway
+            Some("Processor") => {
+                let name = step.get("name").and_then(Yaml::as_str).unwrap_or("?");
+                let Some(definition) = config.get("processors").and_then(|p| p.get(name)) else {
+                    warnings.push(format!("processor {} is not defined, ignored", name));
+                    continue;
+                };
+                let parameters = definition.get("parameters").cloned().unwrap_or(Yaml::Null);
+
+                match definition.get("type").and_then(Yaml::as_str) {
+                    Some("Compressor") => {
+                        let defaults = Compressor::default();
+                        dynamics.compressor = Compressor {
+                            enabled: true,
+                            threshold: number(&parameters, "threshold").unwrap_or(defaults.threshold),
+                            ratio: number(&parameters, "factor").unwrap_or(defaults.ratio),
+                            attack: number(&parameters, "attack").map_or(defaults.attack, |s| s * 1000.),
+                            release: number(&parameters, "release").map_or(defaults.release, |s| s * 1000.),
+                            knee: 0.,
+                            makeupGain: number(&parameters, "makeup_gain").unwrap_or(0.),
+                        };
+                    },
+                    other => warnings.push(format!("processor {}: type {} is not supported, ignored", name, other.unwrap_or("?"))),
+                }
+                continue;
+            },
+            Some(other) => {
+                warnings.push(format!("pipeline step {} is not supported, ignored", other));
+                continue;
+            },
+            None => continue,
+        }
+
+        let targets: Vec<usize> = match (step.get("channel"), step.get("channels")) {
+            (Some(channel), _) => channel.as_u64().into_iter().map(|c| c as usize).collect(),
+            (None, Some(channels)) => channels.as_sequence().into_iter().flatten().filter_map(Yaml::as_u64).map(|c| c as usize).collect(),
+            // v3 applies steps without channels to all of them
+            (None, None) => (0..channels.len()).collect(),
+        };
+        if targets.iter().any(|&c| c >= channels.len()) {
+            warnings.push(format!("only channels 0 to {} are imported", channels.len() - 1));
+        }
+        let targets: Vec<usize> = targets.into_iter().filter(|&c| c < channels.len()).collect();
+        if let Some(&target) = targets.iter().max() {
+            highest = highest.max(target);
+        }
+
+        for name in step.get("names").and_then(Yaml::as_sequence).into_iter().flatten().filter_map(Yaml::as_str) {
+            let Some(definition) = filters.get(name) else {
+                warnings.push(format!("filter {} is not defined, ignored", name));
+                continue;
+            };
+            let parameters = definition.get("parameters").cloned().unwrap_or(Yaml::Null);
+
+            let filter = match definition.get("type").and_then(Yaml::as_str) {
+                Some("Gain") => {
+                    let gain = number(&parameters, "gain").unwrap_or(0.);
+                    let gain = match parameters.get("scale").and_then(Yaml::as_str) {
+                        Some("linear") => 20. * gain.abs().max(1e-6).log10(),
+                        _ => gain,
+                    };
+                    targets.iter().for_each(|&c| channels[c].preamp += gain);
+                    continue;
+                },
+                Some("Biquad") => {
+                    let biquadType = parameters.get("type").and_then(Yaml::as_str).unwrap_or("");
+                    let filterType = match biquadType {
+                        "Peaking" => FilterType::Peak,
+                        "Lowshelf" => FilterType::LowShelf,
+                        "Highshelf" => FilterType::HighShelf,
+                        "Lowpass" => FilterType::LowPass,
+                        "Highpass" => FilterType::HighPass,
+                        "Notch" => FilterType::Notch,
+                        "Bandpass" => FilterType::BandPass,
+                        "Allpass" => FilterType::AllPass,
+                        other => {
+                            warnings.push(format!("filter {}: biquad type {} is not supported, ignored", name, other));
+                            continue;
+                        },
+                    };
+                    let width = match (number(&parameters, "q"), number(&parameters, "slope"), number(&parameters, "bandwidth")) {
+                        (Some(q), _, _) => FilterWidth::Q(q),
+                        (None, Some(slope), _) => FilterWidth::Slope(slope),
+                        (None, None, Some(bandwidth)) => FilterWidth::Q(crate::apo::bandwidthToQ(bandwidth)),
+                        (None, None, None) => FilterWidth::Q(0.707),
+                    };
+
+                    Filter {
+                        filterType,
+                        frequency: number(&parameters, "freq").unwrap_or(1000.),
+                        gain: number(&parameters, "gain").unwrap_or(0.),
+                        width,
+                        enabled: true,
+                    }
+                },
+                Some("Conv") if parameters.get("type").and_then(Yaml::as_str) == Some("Wav") => {
+                    let path = parameters.get("filename").and_then(Yaml::as_str).map(str::to_string);
+                    targets.iter().for_each(|&c| channels[c].convolution = path.clone());
+                    continue;
+                },
+                Some("Limiter") => {
+                    dynamics.limiter = Limiter {
+                        enabled: true,
+                        threshold: number(&parameters, "clip_limit").unwrap_or(0.),
+                        ..Limiter::default()
+                    };
+                    continue;
+                },
+                Some("Delay") => {
+                    let delay = number(&parameters, "delay").unwrap_or(0.);
+                    let delay = match parameters.get("unit").and_then(Yaml::as_str) {
+                        Some("samples") => {
+                            warnings.push(format!("filter {}: delays in samples are taken to be at 48 kHz", name));
+                            delay / 48.
+                        },
+                        // millimetres of sound travel
+                        Some("mm") => delay / 343.,
+                        _ => delay,
+                    };
+                    targets.iter().for_each(|&c| channels[c].delay += delay);
+                    continue;
+                },
+                other => {
+                    warnings.push(format!("filter {}: type {} is not supported, ignored", name, other.unwrap_or("?")));
+                    continue;
+                },
+            };
+
+            targets.iter().for_each(|&c| channels[c].filters.push(filter));
+        }
+    }
+
+    let layout = match highest {
+        0..=1 => ChannelLayout::Stereo,
+        2..=5 => ChannelLayout::Surround51,
+        _ => ChannelLayout::Surround71,
+    };
+    // the channels were numbered like a 7.1 device's, the layout's own numbering gives their speakers
+    channels.truncate(layout.speakers().len());
+    for (channel, &speaker) in channels.iter_mut().zip(layout.speakers()) {
+        channel.speaker = speaker;
+    }
+
+    let mut import = finish(layout, channels, warnings);
+    import.settings.dynamics = dynamics;
+
+    Ok(import)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::GraphicEqPoint;
+
+    fn filter(filterType: FilterType, frequency: f32, gain: f32, q: f32) -> Filter {
+        Filter { filterType, frequency, gain, width: FilterWidth::Q(q), enabled: true }
+    }
+
+    fn channel(speaker: Speaker, preamp: f32) -> EqualiserChannelSettings {
+        EqualiserChannelSettings {
+            preamp,
+            filters: vec![
+                filter(FilterType::LowShelf, 105., 4.5, 0.71),
+                filter(FilterType::Peak, 1234.5, -3.25, 1.41),
+                Filter { enabled: false, ..filter(FilterType::Peak, 3000., 6., 4.) },
+                filter(FilterType::HighShelf, 10_000., -2., 0.7),
+                filter(FilterType::HighPass, 25., 0., 0.5),
+                filter(FilterType::Notch, 60., 0., 30.),
+                filter(FilterType::AllPass, 2000., 0., 0.9),
+            ],
+            ..emptyChannel(speaker)
+        }
+    }
+
+    fn easyEffectsRoundTrip(settings: &EqualiserSettings) -> (EqualiserExport, EqualiserSettings) {
+        let export = toEasyEffects(settings, 48_000.);
+        let import = fromEasyEffects(&export.contents).unwrap();
+        assert!(import.warnings.is_empty(), "{:?}", import.warnings);
+
+        (export, import.settings)
+    }
+
+    #[test]
+    fn easyEffectsLinkedRoundTrips() {
+        let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![channel(Speaker::FrontLeft, -6.5), channel(Speaker::FrontRight, -6.5)]);
+        assert!(settings.linked);
+
+        let (export, imported) = easyEffectsRoundTrip(&settings);
+        assert!(export.warnings.is_empty());
+        assert_eq!(imported, settings);
+    }
+
+    #[test]
+    fn easyEffectsSplitRoundTrips() {
+        let mut right = channel(Speaker::FrontRight, -6.5);
+        right.filters.truncate(2);
+        let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![channel(Speaker::FrontLeft, -6.5), right]);
+        assert!(!settings.linked);
+
+        let (export, imported) = easyEffectsRoundTrip(&settings);
+        assert!(export.warnings.is_empty());
+        assert_eq!(imported, settings);
+    }
+
+    // one input gain can't hold two preamps, the export says so instead of adding a band every round trip
+    #[test]
+    fn easyEffectsSplitPreampsShareTheQuieterOne() {
+        let mut right = channel(Speaker::FrontRight, -6.);
+        right.filters.truncate(2);
+        let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![channel(Speaker::FrontLeft, -3.), right]);
+
+        let (export, imported) = easyEffectsRoundTrip(&settings);
+        assert_eq!(export.warnings.len(), 1, "{:?}", export.warnings);
+        assert!(export.warnings[0].contains("left channel is 3 dB quieter"), "{}", export.warnings[0]);
+        for (original, imported) in settings.channels.iter().zip(&imported.channels) {
+            assert_eq!(imported.preamp, -6.);
+            assert_eq!(imported.filters, original.filters);
+        }
+
+        let (again, reimported) = easyEffectsRoundTrip(&imported);
+        assert!(again.warnings.is_empty());
+        assert_eq!(reimported, imported);
+    }
+
+    #[test]
+    fn camillaDspRoundTrips() {
+        let mut right = channel(Speaker::FrontRight, -4.);
+        right.filters.truncate(2);
+        let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![channel(Speaker::FrontLeft, -6.5), right]);
+
+        let export = toCamillaDsp(&settings, 48_000.);
+        assert!(export.warnings.is_empty(), "{:?}", export.warnings);
+        let imported = fromCamillaDsp(&export.contents).unwrap();
+        assert!(imported.warnings.is_empty(), "{:?}", imported.warnings);
+        for (original, imported) in settings.channels.iter().zip(&imported.settings.channels) {
+            assert_eq!(imported.preamp, original.preamp);
+            assert_eq!(imported.filters, original.filters.iter().filter(|f| f.enabled).cloned().collect::<Vec<_>>());
+        }
+    }
+
+    // a flat curve needs no filters, its level ends up in the preamp gain
+    #[test]
+    fn camillaDspKeepsTheGraphicEqLevel() {
+        let flat = EqualiserChannelSettings {
+            graphicEq: vec![GraphicEqPoint { frequency: 20., gain: -6. }, GraphicEqPoint { frequency: 20_000., gain: -6. }],
+            ..emptyChannel(Speaker::FrontLeft)
+        };
+        let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![flat.clone(), EqualiserChannelSettings { speaker: Speaker::FrontRight, ..flat }]);
+
+        let imported = fromCamillaDsp(&toCamillaDsp(&settings, 48_000.).contents).unwrap().settings;
+        for channel in &imported.channels {
+            assert_eq!(channel.preamp, -6.);
+            assert!(channel.filters.is_empty(), "{:?}", channel.filters);
+        }
+    }
+
+    // the routing and the knee have nowhere to go, the export says they were left out
+    #[test]
+    fn camillaDspWarnsAboutWhatItLeavesOut() {
+        let mut settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![channel(Speaker::FrontLeft, -6.5), channel(Speaker::FrontRight, -6.5)]);
+        let export = toCamillaDsp(&settings, 48_000.);
+        assert!(export.warnings.is_empty(), "{:?}", export.warnings);
+
+        settings.dynamics.compressor = Compressor { enabled: true, knee: 6., ..Compressor::default() };
+        settings.spatial.swap = true;
+        let export = toCamillaDsp(&settings, 48_000.);
+        assert_eq!(export.warnings.len(), 2, "{:?}", export.warnings);
+        assert!(export.warnings[0].contains("needs a mixer"), "{}", export.warnings[0]);
+        assert!(export.warnings[1].contains("6 dB knee"), "{}", export.warnings[1]);
+
+        let imported = fromCamillaDsp(&export.contents).unwrap().settings;
+        let compressor = imported.dynamics.compressor;
+        assert!(compressor.enabled);
+        assert_eq!((compressor.threshold, compressor.ratio, compressor.knee), (settings.dynamics.compressor.threshold, settings.dynamics.compressor.ratio, 0.));
+    }
+}
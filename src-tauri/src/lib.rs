@@ -4,6 +4,17 @@ mod structs;
 mod commands;
 mod util;
 mod statics;
+mod apo;
+mod backend;
+mod response;
+mod presets;
+mod fitting;
+mod profiles;
+mod pipewire;
+mod formats;
+mod convolution;
+mod loudness;
+mod measurement;
 use structs::*;
 use commands::*;
 use statics::*;
@@ -25,11 +36,32 @@ pub fn run() {
             let config = AppConfig::load(app.handle()).unwrap_or_default();
             *VISUALISER_CONFIG.write().unwrap() = config.visualiserSettings.clone();
             *EQUALISER_CONFIG.write().unwrap() = config.equaliserSettings;
+            *EQUALISER_BACKEND_CONFIG.write().unwrap() = config.equaliserBackendSettings;
+            *APP_PROFILE_CONFIG.write().unwrap() = config.appProfileSettings;
+            *EQUALISER_COMPARISON.write().unwrap() = config.equaliserComparison;
             
             app.handle().emit("visualiserUpdate", serde_json::to_string(&config.visualiserSettings).unwrap()).unwrap();
 
             // Tray icon
-            TrayIconBuilder::new()
+            let comparison = EQUALISER_COMPARISON.read().unwrap().status();
+            TrayIconBuilder::with_id(backend::TRAY_ID)
+                .tooltip("SlyshMeFX")
+                .menu(&backend::trayMenu(app.handle(), &comparison)?)
+                .menu_on_left_click(false)
+                .on_menu_event(|app, event| {
+                    let switched = match event.id().as_ref() {
+                        backend::TRAY_BYPASS_ID => {
+                            let bypassed = EQUALISER_COMPARISON.read().unwrap().bypassed;
+                            backend::setBypassed(app, !bypassed).map(|_| ())
+                        },
+                        backend::TRAY_COMPARISON_ID => backend::toggleComparison(app).map(|_| ()),
+                        _ => Ok(()),
+                    };
+
+                    if let Err(e) = switched {
+                        eprintln!("Failed to switch the equaliser: {}", e);
+                    }
+                })
                 .icon(Image::from_path(app.path().resource_dir().unwrap().join("icons/128x128.png")).expect("Failed to load icon."))
                 .on_tray_icon_event(move |tray, event| {
                     match event {
@@ -75,6 +107,21 @@ pub fn run() {
                 })
                 .build(app)?;
 
+            // The PipeWire filter-chain only runs while the app does, so the saved settings are brought back before
+            // device and application presets get the chance to override them
+            let settings = EQUALISER_CONFIG.read().unwrap().clone();
+            if let Err(e) = backend::applyEqualiserSettings(app.handle(), settings) {
+                eprintln!("Failed to restore the equaliser: {}", e);
+            }
+
+            // Per device presets
+            let appHandle = app.handle().clone();
+            std::thread::spawn(move || backend::watchOutputDevice(appHandle));
+
+            // Per application profiles
+            let appHandle = app.handle().clone();
+            std::thread::spawn(move || profiles::watchApplications(appHandle, profiles::ForegroundWatcher));
+
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -88,7 +135,37 @@ pub fn run() {
             close,
             setMonitor,
             getMonitors,
+            getOutputDevice,
+            calibrateLatency,
+            importApoConfig,
+            uninstallEqualiser,
+            getEqualiserStatus,
+            setEqualiserBackendSettings,
+            getEqualiserResponse,
+            getPresets,
+            savePreset,
+            renamePreset,
+            deletePreset,
+            applyPreset,
+            exportPreset,
+            importPreset,
+            importEqualiserProfile,
+            previewEqualiserResponse,
+            setAppProfileSettings,
+            exportEqualiserSettings,
+            importEqualiserSettings,
+            validateImpulseResponse,
+            bypassEqualiser,
+            toggleAB,
+            getComparisonStatus,
+            matchMeasurement,
+            parseTargetCurve,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running application...");
+        .build(tauri::generate_context!())
+        .expect("error while running application...")
+        .run(|_, event| {
+            if let tauri::RunEvent::Exit = event {
+                backend::stopFilterChain();
+            }
+        });
 }
@@ -0,0 +1,163 @@
+use crate::{response::Biquad, structs::{Filter, FilterType, FilterWidth, LoudnessSettings}};
+
+
+
+// ISO 226:2003 table 1, frequency, exponent of loudness perception, magnitude of the linear transfer function and hearing threshold
+const ISO_226: [(f64, f64, f64, f64); 29] = [
+    (20., 0.532, -31.6, 78.5),
+    (25., 0.506, -27.2, 68.7),
+    (31.5, 0.480, -23.0, 59.5),
+    (40., 0.455, -19.1, 51.1),
+    (50., 0.432, -15.9, 44.0),
+    (63., 0.409, -13.0, 37.5),
+    (80., 0.387, -10.3, 31.5),
+    (100., 0.367, -8.1, 26.5),
+    (125., 0.349, -6.2, 22.1),
+    (160., 0.330, -4.5, 17.9),
+    (200., 0.315, -3.1, 14.4),
+    (250., 0.301, -2.0, 11.4),
+    (315., 0.288, -1.1, 8.6),
+    (400., 0.276, -0.4, 6.2),
+    (500., 0.267, 0.0, 4.4),
+    (630., 0.259, 0.3, 3.0),
+    (800., 0.253, 0.5, 2.2),
+    (1000., 0.250, 0.0, 2.4),
+    (1250., 0.246, -2.7, 3.5),
+    (1600., 0.244, -4.1, 1.7),
+    (2000., 0.243, -1.0, -1.3),
+    (2500., 0.243, 1.7, -4.2),
+    (3150., 0.243, 2.5, -6.0),
+    (4000., 0.242, 1.2, -5.4),
+    (5000., 0.242, -2.1, -1.5),
+    (6300., 0.245, -7.1, 6.0),
+    (8000., 0.254, -11.2, 12.6),
+    (10000., 0.271, -10.7, 13.9),
+    (12500., 0.301, -3.1, 12.3),
+];
+// the range the standard's contours are defined over
+const MIN_PHON: f64 = 20.;
+const MAX_PHON: f64 = 90.;
+const MAX_BOOST: f32 = 12.;
+const LOW_SHELF_FREQUENCIES: [f32; 6] = [60., 80., 100., 125., 160., 200.];
+const HIGH_SHELF_FREQUENCIES: [f32; 4] = [4000., 5000., 6300., 8000.];
+
+// Sound pressure level at which a tone sounds as loud as a 1 kHz tone at `phon`, for each row of the table
+fn contour(phon: f64) -> [f64; 29] {
+    ISO_226.map(|(_, af, lu, tf)| {
+        let a = 4.47e-3 * (10f64.powf(0.025 * phon) - 1.15) + (0.4 * 10f64.powf((tf + lu) / 10. - 9.)).powf(af);
+        10. / af * a.log10() - lu + 94.
+    })
+}
+
+// dB a listener at `level` misses compared to `reference`, relative to 1 kHz and interpolated on a log frequency scale
+pub fn compensation(level: f64, reference: f64, frequency: f64) -> f64 {
+    let (level, reference) = (level.clamp(MIN_PHON, MAX_PHON), reference.clamp(MIN_PHON, MAX_PHON));
+    let (quiet, loud) = (contour(level), contour(reference));
+    let difference = |i: usize| (quiet[i] - level) - (loud[i] - reference);
+
+    let last = ISO_226.len() - 1;
+    match ISO_226.iter().position(|&(f, ..)| f >= frequency) {
+        Some(0) => difference(0),
+        None => difference(last),
+        Some(i) => {
+            let (low, high) = (ISO_226[i - 1].0, ISO_226[i].0);
+            let t = (frequency / low).ln() / (high / low).ln();
+            difference(i - 1) + (difference(i) - difference(i - 1)) * t
+        },
+    }
+}
+
+// The shelf out of `frequencies` with the least squared error against the curve over `range` at `sampleRate`, gains
+// are near enough proportional to a shelf's response for the best one to be a projection
+fn fitShelf(filterType: FilterType, frequencies: &[f32], range: (f64, f64), curve: &dyn Fn(f64) -> f64, sampleRate: f64) -> Option<Filter> {
+    let grid: Vec<f64> = (0..48).map(|i| range.0 * (range.1 / range.0).powf(i as f64 / 47.)).collect();
+    let target: Vec<f64> = grid.iter().map(|&f| curve(f)).collect();
+
+    frequencies
+        .iter()
+        .map(|&frequency| {
+            let unit = Filter {
+                filterType,
+                frequency,
+                gain: 1.,
+                width: FilterWidth::Q(0.707),
+                enabled: true,
+            };
+            let biquad = Biquad::fromFilter(&unit, sampleRate);
+            let shape: Vec<f64> = grid.iter().map(|&f| biquad.magnitudeDb(f, sampleRate)).collect();
+
+            let gain = target.iter().zip(&shape).map(|(t, s)| t * s).sum::<f64>() / shape.iter().map(|s| s * s).sum::<f64>();
+            let error: f64 = target.iter().zip(&shape).map(|(t, s)| (t - gain * s).powi(2)).sum();
+            (Filter { gain: (gain as f32).clamp(0., MAX_BOOST), ..unit }, error)
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(filter, _)| filter)
+        .filter(|filter| filter.gain >= 0.1)
+}
+
+// Shelving boosts making up for the ear's lost sensitivity when a channel plays `preamp` dB below the reference level,
+// fitted at the rate they'll run at since the high shelf sits close enough to Nyquist to be warped
+pub fn loudnessFilters(settings: &LoudnessSettings, preamp: f32, sampleRate: f64) -> Vec<Filter> {
+    if !settings.enabled || preamp >= 0. {
+        return vec![];
+    }
+
+    let (level, reference) = ((settings.referenceLevel + preamp) as f64, settings.referenceLevel as f64);
+    let curve = |frequency| compensation(level, reference, frequency);
+
+    [
+        fitShelf(FilterType::LowShelf, &LOW_SHELF_FREQUENCIES, (20., 1000.), &curve, sampleRate),
+        fitShelf(FilterType::HighShelf, &HIGH_SHELF_FREQUENCIES, (2000., 16000.), &curve, sampleRate),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the 40 phon contour as ISO 226:2003 tabulates it
+    const CONTOUR_40: [f64; 29] = [
+        99.85, 93.94, 88.17, 82.63, 77.78, 73.08, 68.48, 64.37, 60.59, 56.70, 53.41, 50.40, 47.58, 44.98, 43.05, 41.34, 40.06, 40.01, 41.82,
+        42.51, 39.23, 36.51, 35.61, 36.65, 40.01, 45.83, 51.80, 54.28, 51.49,
+    ];
+
+    #[test]
+    fn contourMatchesTheStandard() {
+        for ((frequency, ..), (level, expected)) in ISO_226.iter().zip(contour(40.).into_iter().zip(CONTOUR_40)) {
+            assert!((level - expected).abs() < 0.01, "{} Hz: {} dB instead of {}", frequency, level, expected);
+        }
+        // a 1 kHz tone is as loud as itself at every level
+        for phon in [20., 40., 60., 80., 90.] {
+            assert!((contour(phon)[17] - phon).abs() < 0.02, "{} phon", phon);
+        }
+    }
+
+    #[test]
+    fn compensationIsRelative() {
+        assert_eq!(compensation(83., 83., 50.), 0.);
+        assert!(compensation(40., 83., 1000.).abs() < 0.02);
+        // quieter listening loses the most at the bottom, 20 Hz between 40 and 80 phon differs by about 21 dB
+        assert!((compensation(40., 80., 20.) - 20.87).abs() < 0.05);
+        assert!(compensation(40., 80., 50.) > compensation(40., 80., 200.));
+        // outside the table the ends hold, outside the standard's range the levels are clamped
+        assert_eq!(compensation(40., 80., 10.), compensation(40., 80., 20.));
+        assert_eq!(compensation(0., 100., 100.), compensation(20., 90., 100.));
+    }
+
+    #[test]
+    fn filtersBoostTheEnds() {
+        let settings = LoudnessSettings { enabled: true, referenceLevel: 83. };
+        assert!(loudnessFilters(&settings, 0., 48_000.).is_empty());
+        assert!(loudnessFilters(&LoudnessSettings { enabled: false, ..settings }, -20., 48_000.).is_empty());
+
+        let filters = loudnessFilters(&settings, -30., 48_000.);
+        let low = filters.iter().find(|f| f.filterType == FilterType::LowShelf).unwrap();
+        assert!(low.gain > 3. && low.gain <= MAX_BOOST, "{:?}", low);
+        assert!(LOW_SHELF_FREQUENCIES.contains(&low.frequency));
+        assert!(loudnessFilters(&settings, -10., 48_000.).iter().all(|f| f.gain < low.gain || f.filterType != FilterType::LowShelf));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{fitting::fitFilters, response::graphicEqGain, structs::{Filter, FitLimits, GraphicEqPoint, TargetCurve}};
+
+
+
+// measurements are averaged into bins this fine before smoothing, REW's linear exports have tens of thousands of points
+const BINS_PER_OCTAVE: f64 = 48.;
+// enough to keep room modes and driver resonances but lose the comb filtering nobody should EQ
+const SMOOTHING_OCTAVES: f64 = 1. / 6.;
+
+// Harman over-ear 2018 relative to a flat response, sampled from the published curve
+const HARMAN_OVER_EAR: [(f32, f32); 22] = [
+    (20., 6.0),
+    (30., 6.0),
+    (50., 5.6),
+    (80., 4.6),
+    (100., 4.0),
+    (150., 2.2),
+    (200., 1.0),
+    (300., 0.2),
+    (500., 0.0),
+    (1000., 0.0),
+    (1500., 1.0),
+    (2000., 3.0),
+    (2500., 6.5),
+    (3000., 8.5),
+    (4000., 8.0),
+    (5000., 6.5),
+    (6000., 4.5),
+    (8000., 2.5),
+    (10000., 1.0),
+    (12000., -1.0),
+    (16000., -4.0),
+    (20000., -8.0),
+];
+
+fn parseNumber(token: &str) -> Option<f32> {
+    token.trim_matches(',').replace(',', ".").parse().ok().filter(|n: &f32| n.is_finite())
+}
+
+// Averages the points falling in each bin, bins without any are left out
+fn binned(points: &[GraphicEqPoint]) -> Vec<GraphicEqPoint> {
+    let mut bins: Vec<(i64, f64, f64, usize)> = vec![];
+
+    for point in points {
+        let bin = ((point.frequency as f64).log2() * BINS_PER_OCTAVE).floor() as i64;
+        match bins.last_mut() {
+            Some(last) if last.0 == bin => {
+                last.1 += (point.frequency as f64).ln();
+                last.2 += point.gain as f64;
+                last.3 += 1;
+            },
+            _ => bins.push((bin, (point.frequency as f64).ln(), point.gain as f64, 1)),
+        }
+    }
+
+    bins.into_iter()
+        .map(|(_, frequency, gain, count)| GraphicEqPoint {
+            frequency: (frequency / count as f64).exp() as f32,
+            gain: (gain / count as f64) as f32,
+        })
+        .collect()
+}
+
+// Fractional octave smoothing, each point becomes the mean of those within `octaves` / 2 of it
+fn smoothed(points: &[GraphicEqPoint], octaves: f64) -> Vec<GraphicEqPoint> {
+    points
+        .iter()
+        .map(|point| {
+            let window: Vec<f32> = points
+                .iter()
+                .filter(|p| (p.frequency as f64 / point.frequency as f64).log2().abs() <= octaves / 2.)
+                .map(|p| p.gain)
+                .collect();
+
+            GraphicEqPoint { frequency: point.frequency, gain: window.iter().sum::<f32>() / window.len() as f32 }
+        })
+        .collect()
+}
+
+// Reads REW's "Export measurement as text" files and CSVs like AutoEq's, the first two columns being frequency and level.
+// Comments and headers are skipped, fields can be separated by tabs, spaces, semicolons or commas.
+pub fn parseMeasurement(contents: &str) -> Result<(Vec<GraphicEqPoint>, Vec<String>), String> {
+    let mut points = vec![];
+    let mut warnings = vec![];
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['*', '#']) {
+            continue;
+        }
+
+        // decimal commas only come with another separator
+        let fields: Vec<&str> = if line.contains(['\t', ';', ' ']) {
+            line.split(['\t', ';', ' ']).filter(|f| !f.is_empty()).collect()
+        } else {
+            line.split(',').collect()
+        };
+        let (Some(frequency), Some(level)) = (fields.first().and_then(|f| parseNumber(f)), fields.get(1).and_then(|f| parseNumber(f))) else {
+            // column headers come before the data
+            if !points.is_empty() {
+                warnings.push(format!("line {}: {} is not a frequency and level, ignored", number + 1, line));
+            }
+            continue;
+        };
+        if frequency <= 0. {
+            warnings.push(format!("line {}: frequency {} is not positive, ignored", number + 1, frequency));
+            continue;
+        }
+
+        points.push(GraphicEqPoint { frequency, gain: level });
+    }
+
+    if points.len() < 2 {
+        return Err("the file has no frequency response in it".into());
+    }
+    points.sort_by(|a, b| a.frequency.total_cmp(&b.frequency));
+
+    Ok((smoothed(&binned(&points), SMOOTHING_OCTAVES), warnings))
+}
+
+pub fn targetPoints(target: &TargetCurve) -> Vec<GraphicEqPoint> {
+    match target {
+        TargetCurve::Flat => vec![GraphicEqPoint { frequency: 1000., gain: 0. }],
+        TargetCurve::HarmanOverEar => HARMAN_OVER_EAR.iter().map(|&(frequency, gain)| GraphicEqPoint { frequency, gain }).collect(),
+        TargetCurve::Custom(points) => points.clone(),
+    }
+}
+
+// Fits `bands` peaking filters taking `measurement` to `target` at `sampleRate`, returning them with the RMS error in dB.
+// Only the range that was measured is corrected, levels are left to the preamp since only the shapes are compared.
+pub fn matchTarget(measurement: &[GraphicEqPoint], target: &TargetCurve, bands: usize, limits: &FitLimits, sampleRate: f64) -> (Vec<Filter>, f64) {
+    let (Some(first), Some(last)) = (measurement.first(), measurement.last()) else {
+        return (vec![], 0.);
+    };
+    let limits = FitLimits {
+        minFrequency: limits.minFrequency.max(first.frequency),
+        maxFrequency: limits.maxFrequency.min(last.frequency),
+        ..*limits
+    };
+
+    let target = targetPoints(target);
+    let correction: Vec<GraphicEqPoint> = measurement
+        .iter()
+        .map(|p| GraphicEqPoint {
+            frequency: p.frequency,
+            gain: (graphicEqGain(&target, p.frequency as f64) - p.gain as f64) as f32,
+        })
+        .collect();
+
+    // the measurement's level depends on where the microphone was, only the shape is corrected
+    let fit = fitFilters(&correction, bands, &limits, sampleRate);
+    (fit.filters, fit.error)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{response::Biquad, structs::{FilterType, FilterWidth}};
+
+    fn filter(filterType: FilterType, frequency: f32, gain: f32, q: f32) -> Filter {
+        Filter { filterType, frequency, gain, width: FilterWidth::Q(q), enabled: true }
+    }
+
+    const SAMPLE_RATE: f64 = 48_000.;
+
+    fn responseDb(filters: &[Filter], frequency: f64) -> f64 {
+        filters.iter().map(|f| Biquad::fromFilter(f, SAMPLE_RATE).magnitudeDb(frequency, SAMPLE_RATE)).sum()
+    }
+
+    // 1/48 octave points from 20 Hz to 20 kHz
+    fn frequencies() -> Vec<f32> {
+        (0..=478).map(|i| 20. * (i as f32 / 48.).exp2()).collect()
+    }
+
+    // A headphone measuring as `target` with `deviation` on top of it, `offset` dB louder
+    fn measured(target: &TargetCurve, deviation: &[Filter], offset: f32) -> Vec<GraphicEqPoint> {
+        let target = targetPoints(target);
+        frequencies()
+            .into_iter()
+            .map(|frequency| GraphicEqPoint {
+                frequency,
+                gain: (graphicEqGain(&target, frequency as f64) + responseDb(deviation, frequency as f64)) as f32 + offset,
+            })
+            .collect()
+    }
+
+    // the fitted peaks undo the deviation up to a level, which is the preamp's to set
+    fn assertRecovers(target: TargetCurve) {
+        let deviation = [
+            filter(FilterType::LowShelf, 90., 5., 0.7),
+            filter(FilterType::Peak, 250., -4., 1.2),
+            filter(FilterType::Peak, 3200., 7., 2.5),
+            filter(FilterType::HighShelf, 9000., -4., 0.7),
+        ];
+        let limits = FitLimits::default();
+        let (filters, error) = matchTarget(&measured(&target, &deviation, 83.5), &target, 10, &limits, SAMPLE_RATE);
+        assert!(!filters.is_empty() && filters.len() <= 10);
+        assert!(error < 0.25, "{:?}: {} dB RMS", target, error);
+
+        let residual: Vec<f64> = frequencies()
+            .into_iter()
+            .filter(|&f| (limits.minFrequency..=limits.maxFrequency).contains(&f))
+            .map(|f| responseDb(&deviation, f as f64) + responseDb(&filters, f as f64))
+            .collect();
+        let mut sorted = residual.clone();
+        sorted.sort_by(f64::total_cmp);
+        let level = sorted[sorted.len() / 2];
+        for (f, r) in frequencies().iter().zip(&residual) {
+            assert!((r - level).abs() < 1., "{:?}: {} dB left at {} Hz", target, r - level, f);
+        }
+    }
+
+    #[test]
+    fn recoversFlat() {
+        assertRecovers(TargetCurve::Flat);
+    }
+
+    #[test]
+    fn recoversHarmanOverEar() {
+        assertRecovers(TargetCurve::HarmanOverEar);
+    }
+
+    #[test]
+    fn recoversCustom() {
+        assertRecovers(TargetCurve::Custom(vec![
+            GraphicEqPoint { frequency: 20., gain: 4. },
+            GraphicEqPoint { frequency: 1000., gain: 0. },
+            GraphicEqPoint { frequency: 20000., gain: -5. },
+        ]));
+    }
+
+    // the same response written the ways REW and spreadsheets write it
+    #[test]
+    fn parsesEveryFormat() {
+        let levels: Vec<(f32, f32)> = frequencies().into_iter().step_by(4).map(|f| (f, 75.25 + (f / 1000.).log2().sin())).collect();
+        let rew = format!(
+            "* Measurement data measured by REW V5.20.13\n* Freq(Hz) SPL(dB) Phase(degrees)\n{}",
+            levels.iter().map(|(f, l)| format!("{:.3} {:.3} -12.000\n", f, l)).collect::<String>()
+        );
+        let csv = format!("frequency,raw\n{}", levels.iter().map(|(f, l)| format!("{:.3},{:.3}\n", f, l)).collect::<String>());
+        let decimalComma = format!(
+            "Frequenz;Pegel\n{}",
+            levels.iter().map(|(f, l)| format!("{:.3};{:.3}\n", f, l).replace('.', ",")).collect::<String>()
+        );
+
+        let (points, warnings) = parseMeasurement(&rew).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert!((points[0].frequency - 20.).abs() < 0.01 && (points.last().unwrap().frequency - levels.last().unwrap().0).abs() < 0.01);
+        for point in &points {
+            let level = 75.25 + (point.frequency / 1000.).log2().sin();
+            assert!((point.gain - level).abs() < 0.05, "{} dB at {} Hz, {} dB measured", point.gain, point.frequency, level);
+        }
+
+        for contents in [csv, decimalComma] {
+            let (parsed, warnings) = parseMeasurement(&contents).unwrap();
+            assert!(warnings.is_empty(), "{:?}", warnings);
+            assert_eq!(parsed, points);
+        }
+    }
+
+    #[test]
+    fn warnsAboutBrokenLines() {
+        let (points, warnings) = parseMeasurement("Freq,dB\n20,1\n-5,2\n1000,0\nnoise\n20000,-3\n").unwrap();
+        assert_eq!(points.len(), 3);
+        assert_eq!(warnings, vec!["line 3: frequency -5 is not positive, ignored", "line 5: noise is not a frequency and level, ignored"]);
+
+        assert!(parseMeasurement("* nothing measured\n").is_err());
+    }
+}
@@ -0,0 +1,555 @@
+use std::path::PathBuf;
+
+use crate::{convolution, fitting::parametricFilters, response::slopeToQ, structs::{ChannelLayout, Compressor, DynamicsSettings, DynamicsSupport, EqualiserChannelSettings, EqualiserSettings, Filter, FilterType, FilterWidth, Limiter, SpatialSettings, Speaker}};
+
+
+
+pub const CONFIG_FILE: &str = "slyshmefx-equaliser.conf";
+// the filter-chain's sink, the node its controls are set on
+pub const SINK_NODE: &str = "effect_input.slyshmefx";
+// swh-plugins' stereo compressor and lookahead limiter
+const COMPRESSOR_PLUGIN: &str = "sc4_1882";
+const LIMITER_PLUGIN: &str = "fast_lookahead_limiter_1913";
+const LADSPA_DIRS: [&str; 5] = ["/usr/lib/ladspa", "/usr/lib64/ladspa", "/usr/local/lib/ladspa", "/usr/lib/x86_64-linux-gnu/ladspa", "/usr/lib/aarch64-linux-gnu/ladspa"];
+
+// Whether a LADSPA plugin is where PipeWire looks for it, $LADSPA_PATH when it's set and the usual directories otherwise
+fn ladspaPluginInstalled(plugin: &str) -> bool {
+    let dirs: Vec<PathBuf> = match std::env::var_os("LADSPA_PATH") {
+        Some(path) => std::env::split_paths(&path).collect(),
+        None => LADSPA_DIRS.iter().map(PathBuf::from).collect(),
+    };
+
+    dirs.iter().any(|dir| dir.join(format!("{}.so", plugin)).is_file())
+}
+
+pub fn dynamicsSupport() -> DynamicsSupport {
+    DynamicsSupport {
+        compressor: ladspaPluginInstalled(COMPRESSOR_PLUGIN),
+        limiter: ladspaPluginInstalled(LIMITER_PLUGIN),
+    }
+}
+
+fn label(filterType: FilterType) -> &'static str {
+    match filterType {
+        FilterType::Peak => "bq_peaking",
+        FilterType::LowShelf => "bq_lowshelf",
+        FilterType::HighShelf => "bq_highshelf",
+        FilterType::LowPass => "bq_lowpass",
+        FilterType::HighPass => "bq_highpass",
+        FilterType::Notch => "bq_notch",
+        FilterType::BandPass => "bq_bandpass",
+        FilterType::AllPass => "bq_allpass",
+    }
+}
+
+struct Node {
+    name: String,
+    // "type = builtin" and the rest of what tells the node apart, the controls are set separately
+    kind: String,
+    controls: Vec<(String, f32)>,
+}
+
+// A filter-chain graph, kept apart from its config so the backend can tell control changes from topology changes
+pub struct FilterChain {
+    nodes: Vec<Node>,
+    links: Vec<(String, String)>,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    layout: ChannelLayout,
+}
+impl FilterChain {
+    fn new(layout: ChannelLayout) -> Self {
+        Self { nodes: vec![], links: vec![], inputs: vec![], outputs: vec![], layout }
+    }
+
+    fn node(&mut self, name: &str, kind: String, controls: Vec<(String, f32)>) {
+        self.nodes.push(Node { name: name.into(), kind, controls });
+    }
+
+    fn biquad(&mut self, name: &str, filterType: FilterType, frequency: f32, q: f32, gain: f32) {
+        self.node(
+            name,
+            format!("type = builtin name = {} label = {}", name, label(filterType)),
+            vec![("Freq".into(), frequency), ("Q".into(), q), ("Gain".into(), gain)],
+        );
+    }
+
+    fn delay(&mut self, name: &str, seconds: f32) {
+        self.node(
+            name,
+            format!("type = builtin name = {} label = delay config = {{ \"max-delay\" = {} }}", name, seconds.ceil().max(1.)),
+            vec![("Delay (s)".into(), seconds)],
+        );
+    }
+
+    fn mixer(&mut self, name: &str, gains: &[f32]) {
+        let controls = gains.iter().enumerate().map(|(i, &gain)| (format!("Gain {}", i + 1), gain)).collect();
+        self.node(name, format!("type = builtin name = {} label = mixer", name), controls);
+    }
+
+    fn link(&mut self, output: &str, input: &str) {
+        self.links.push((output.into(), input.into()));
+    }
+
+    // Everything restarting the filter would change, the values of the controls aside
+    pub fn topology(&self) -> String {
+        self.render(false)
+    }
+
+    // "node:Control" and its value for every control, how PipeWire names them when setting them in place
+    pub fn controls(&self) -> Vec<(String, f32)> {
+        self.nodes
+            .iter()
+            .flat_map(|node| node.controls.iter().map(move |(control, value)| (format!("{}:{}", node.name, control), *value)))
+            .collect()
+    }
+
+    // A standalone PipeWire config running the graph as a filter-chain sink with the layout's channels
+    pub fn config(&self) -> String {
+        self.render(true)
+    }
+
+    fn render(&self, values: bool) -> String {
+        let nodes: String = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let controls: Vec<String> = node
+                    .controls
+                    .iter()
+                    .map(|(control, value)| if values { format!("\"{}\" = {}", control, value) } else { format!("\"{}\"", control) })
+                    .collect();
+
+                match controls.is_empty() {
+                    true => format!("                    {{ {} }}\n", node.kind),
+                    false => format!("                    {{ {} control = {{ {} }} }}\n", node.kind, controls.join(" ")),
+                }
+            })
+            .collect();
+        let links: String = self
+            .links
+            .iter()
+            .map(|(output, input)| format!("                    {{ output = \"{}\" input = \"{}\" }}\n", output, input))
+            .collect();
+        let quoted = |ports: &[String]| ports.iter().map(|port| format!("\"{}\"", port)).collect::<Vec<_>>().join(" ");
+
+        moduleConfig(&nodes, &links, &quoted(&self.inputs), &quoted(&self.outputs), self.layout)
+    }
+}
+
+// The Props param setting the controls in place with pw-cli, `{ params = [ "eq1:Gain" 3 ] }`
+pub fn propsParam(controls: &[(String, f32)]) -> String {
+    let params: Vec<String> = controls.iter().map(|(control, value)| format!("\"{}\" {}", control, value)).collect();
+    format!("{{ params = [ {} ] }}", params.join(" "))
+}
+
+// The plugins' controls only go so far, settings beyond them are clamped and described in `clamped`
+fn limit(setting: &str, value: f32, min: f32, max: f32, unit: &str, clamped: &mut Vec<String>) -> f32 {
+    let limited = value.clamp(min, max);
+    if limited != value {
+        clamped.push(format!("{} {}{} is limited to {}{}", setting, value, unit, limited, unit));
+    }
+
+    limited
+}
+
+fn compressorControls(compressor: &Compressor, clamped: &mut Vec<String>) -> Vec<(String, f32)> {
+    vec![
+        ("RMS/peak".into(), 0.),
+        ("Attack time (ms)".into(), limit("Compressor attack", compressor.attack, 1.5, 400., " ms", clamped)),
+        ("Release time (ms)".into(), limit("Compressor release", compressor.release, 2., 800., " ms", clamped)),
+        ("Threshold level (dB)".into(), limit("Compressor threshold", compressor.threshold, -30., 0., " dB", clamped)),
+        ("Ratio (1:n)".into(), limit("Compressor ratio", compressor.ratio, 1., 20., ":1", clamped)),
+        ("Knee radius (dB)".into(), limit("Compressor knee", compressor.knee, 1., 10., " dB", clamped)),
+        ("Makeup gain (dB)".into(), limit("Compressor makeup gain", compressor.makeupGain, 0., 24., " dB", clamped)),
+    ]
+}
+
+fn limiterControls(limiter: &Limiter, clamped: &mut Vec<String>) -> Vec<(String, f32)> {
+    vec![
+        ("Input gain (dB)".into(), 0.),
+        ("Limit (dB)".into(), limit("Limiter ceiling", limiter.threshold, -20., 0., " dB", clamped)),
+        ("Release time (s)".into(), limit("Limiter release", limiter.release, 10., 2000., " ms", clamped) / 1000.),
+    ]
+}
+
+// What of the enabled dynamics the plugins can't go as far as, empty when they run as set
+pub fn clampedDynamics(dynamics: &DynamicsSettings) -> Vec<String> {
+    let mut clamped = vec![];
+    if dynamics.compressor.enabled {
+        compressorControls(&dynamics.compressor, &mut clamped);
+    }
+    if dynamics.limiter.enabled {
+        limiterControls(&dynamics.limiter, &mut clamped);
+    }
+
+    clamped
+}
+
+// The compressor and limiter after every channel's chain, taking `lasts`' outputs and replacing them with theirs.
+// The plugins are stereo with their gain reduction linked, so each pair of channels in the layout's order shares one,
+// which for stereo is all of them and keeps the front, centre and surround pairs' images steady otherwise.
+fn dynamicsGraph(dynamics: &DynamicsSettings, speakers: &[Speaker], lasts: &mut [String], chain: &mut FilterChain) {
+    let mut stages = vec![];
+    if dynamics.compressor.enabled {
+        stages.push(("compressor", format!("plugin = {} label = sc4", COMPRESSOR_PLUGIN), compressorControls(&dynamics.compressor, &mut vec![]), ["Left input", "Right input"], ["Left output", "Right output"]));
+    }
+    if dynamics.limiter.enabled {
+        stages.push(("limiter", format!("plugin = {} label = fastLookaheadLimiter", LIMITER_PLUGIN), limiterControls(&dynamics.limiter, &mut vec![]), ["Input 1", "Input 2"], ["Output 1", "Output 2"]));
+    }
+
+    for (stage, plugin, controls, inputs, outputs) in stages {
+        for (pair, lasts) in speakers.chunks(2).zip(lasts.chunks_mut(2)) {
+            let name = format!("{}_{}", pair.iter().map(|s| s.short().to_ascii_lowercase()).collect::<Vec<_>>().join("_"), stage);
+
+            chain.node(&name, format!("type = ladspa name = {} {}", name, plugin), controls.clone());
+            for (i, last) in lasts.iter_mut().enumerate() {
+                chain.link(last, &format!("{}:{}", name, inputs[i]));
+                *last = format!("{}:{}", name, outputs[i]);
+            }
+        }
+    }
+}
+
+// Nodes and links of one channel's chain, returning its first node's name and its last output port
+fn channelGraph(channel: &EqualiserChannelSettings, prefix: &str, sampleRate: f64, chain: &mut FilterChain) -> (String, String) {
+    let (filters, level) = parametricFilters(channel, sampleRate);
+    let filters: Vec<Filter> = filters.into_iter().filter(|f| f.enabled).collect();
+
+    // a high shelf at 0 Hz is a plain gain stage
+    let first = format!("{}preamp", prefix);
+    chain.biquad(&first, FilterType::HighShelf, 0., 1., channel.preamp + level + channel.trim);
+
+    let mut last = format!("{}:Out", first);
+    for (i, filter) in filters.iter().enumerate() {
+        let q = match filter.width {
+            FilterWidth::Q(q) => q,
+            // the builtin shelves only take a Q
+            FilterWidth::Slope(slope) => slopeToQ(slope, filter.gain),
+        };
+        let name = format!("{}eq{}", prefix, i + 1);
+
+        chain.biquad(&name, filter.filterType, filter.frequency, q, filter.gain);
+        chain.link(&last, &format!("{}:In", name));
+        last = format!("{}:Out", name);
+    }
+
+    if let Some(path) = &channel.convolution {
+        let name = format!("{}convolver", prefix);
+        // multichannel IRs hold a channel per speaker, mono ones are used as they are
+        let irChannel = convolution::load(std::path::Path::new(path)).map_or(0, |ir| channel.speaker.irChannel(ir.channels.len()));
+
+        chain.node(
+            &name,
+            format!("type = builtin name = {} label = convolver config = {{ filename = \"{}\" channel = {} }}", name, path.replace('\\', "/"), irChannel),
+            vec![],
+        );
+        chain.link(&last, &format!("{}:In", name));
+        last = format!("{}:Out", name);
+    }
+
+    if channel.delay > 0. {
+        let name = format!("{}delay", prefix);
+
+        chain.delay(&name, channel.delay / 1000.);
+        chain.link(&last, &format!("{}:In", name));
+        last = format!("{}:Out", name);
+    }
+
+    (first, last)
+}
+
+// The routing matrix and crossfeed in front of the channels' chains, returning the input nodes and each channel's last node
+fn spatialGraph(spatial: &SpatialSettings, chain: &mut FilterChain) -> ([String; 2], [String; 2]) {
+    // graph inputs can only feed one node, the copies fan them out to both mixers
+    let inputs = ["in_l".to_string(), "in_r".to_string()];
+    for name in &inputs {
+        chain.node(name, format!("type = builtin name = {} label = copy", name), vec![]);
+    }
+
+    let matrix = spatial.routingMatrix();
+    let mut lasts = ["route_l".to_string(), "route_r".to_string()];
+    for (name, gains) in lasts.iter().zip(matrix) {
+        chain.mixer(name, &gains);
+        chain.link("in_l:Out", &format!("{}:In 1", name));
+        chain.link("in_r:Out", &format!("{}:In 2", name));
+    }
+
+    if spatial.crossfeed {
+        let (direct, cross) = spatial.crossfeedGains();
+        let routed = lasts.clone();
+
+        for (i, side) in ["l", "r"].iter().enumerate() {
+            // each ear hears the other channel low passed and a little late
+            let (lowPass, delay, mix) = (format!("xf_{}_lp", side), format!("xf_{}_delay", side), format!("xf_{}_mix", side));
+            chain.biquad(&lowPass, FilterType::LowPass, spatial.crossfeedCutoff, 0.707, 0.);
+            chain.delay(&delay, SpatialSettings::CROSSFEED_DELAY_MS / 1000.);
+            chain.mixer(&mix, &[direct, cross]);
+
+            chain.link(&format!("{}:Out", routed[1 - i]), &format!("{}:In", lowPass));
+            chain.link(&format!("{}:Out", lowPass), &format!("{}:In", delay));
+            chain.link(&format!("{}:Out", routed[i]), &format!("{}:In 1", mix));
+            chain.link(&format!("{}:Out", delay), &format!("{}:In 2", mix));
+            lasts[i] = mix;
+        }
+    }
+
+    (inputs, lasts)
+}
+
+// The equaliser as a filter-chain, the dynamics are expected to be limited to what `dynamicsSupport` found installed.
+// Linked channels share one graph which PipeWire copies per channel, otherwise, or when an IR needs picking a
+// channel from, the channels are mixed together, trimmed differently or compressed together, each channel gets its own.
+pub fn filterChain(settings: &EqualiserSettings, sampleRate: f64) -> FilterChain {
+    let settings = &settings.compensated(sampleRate);
+    let mut chain = FilterChain::new(settings.layout);
+    let first = &settings.channels[0];
+    let shared = settings.isLinked()
+        && first.convolution.is_none()
+        && settings.spatial.isNeutral()
+        && settings.channels.iter().all(|c| c.trim == first.trim && c.delay == first.delay)
+        && !settings.dynamics.compressor.enabled
+        && !settings.dynamics.limiter.enabled;
+
+    if shared {
+        let (first, last) = channelGraph(first, "", sampleRate, &mut chain);
+        chain.inputs = vec![format!("{}:In", first)];
+        chain.outputs = vec![last];
+    } else {
+        let graphs: Vec<(String, String)> = settings
+            .channels
+            .iter()
+            .map(|c| channelGraph(c, &format!("{}_", c.speaker.short().to_ascii_lowercase()), sampleRate, &mut chain))
+            .collect();
+        let mut inputs: Vec<String> = graphs.iter().map(|(first, _)| first.clone()).collect();
+
+        // the routing only covers the front pair, which every layout starts with
+        if !settings.spatial.isNeutral() {
+            let (spatialInputs, routed) = spatialGraph(&settings.spatial, &mut chain);
+            for (i, (routed, input)) in routed.into_iter().zip(spatialInputs).enumerate() {
+                chain.link(&format!("{}:Out", routed), &format!("{}:In", graphs[i].0));
+                inputs[i] = input;
+            }
+        }
+
+        let mut lasts: Vec<String> = graphs.into_iter().map(|(_, last)| last).collect();
+        let speakers: Vec<Speaker> = settings.channels.iter().map(|c| c.speaker).collect();
+        dynamicsGraph(&settings.dynamics, &speakers, &mut lasts, &mut chain);
+
+        chain.inputs = inputs.iter().map(|name| format!("{}:In", name)).collect();
+        chain.outputs = lasts;
+    }
+
+    chain
+}
+
+// Passes the audio through untouched, so bypassing doesn't take the sink away from the streams playing to it
+pub fn bypassChain(layout: ChannelLayout) -> FilterChain {
+    let mut chain = FilterChain::new(layout);
+    chain.node("copy", "type = builtin name = copy label = copy".into(), vec![]);
+    chain.inputs = vec!["copy:In".into()];
+    chain.outputs = vec!["copy:Out".into()];
+
+    chain
+}
+
+fn moduleConfig(nodes: &str, links: &str, inputs: &str, outputs: &str, layout: ChannelLayout) -> String {
+    let positions: Vec<&str> = layout.speakers().iter().map(|s| s.pipeWirePosition()).collect();
+
+    format!(
+        r#"# Generated by SlyshMeFX, changes are overwritten
+
+context.spa-libs = {{
+    audio.convert.* = audioconvert/libspa-audioconvert
+    support.*       = support/libspa-support
+}}
+
+context.modules = [
+    {{ name = libpipewire-module-rt flags = [ ifexists nofail ] }}
+    {{ name = libpipewire-module-protocol-native }}
+    {{ name = libpipewire-module-client-node }}
+    {{ name = libpipewire-module-adapter }}
+    {{ name = libpipewire-module-filter-chain
+        args = {{
+            node.description = "SlyshMeFX Equaliser"
+            media.name       = "SlyshMeFX Equaliser"
+            filter.graph = {{
+                nodes = [
+{}                ]
+                links = [
+{}                ]
+                inputs  = [ {} ]
+                outputs = [ {} ]
+            }}
+            audio.channels = {}
+            audio.position = [ {} ]
+            capture.props = {{
+                node.name   = "{}"
+                media.class = Audio/Sink
+            }}
+            playback.props = {{
+                node.name    = "effect_output.slyshmefx"
+                node.passive = true
+            }}
+        }}
+    }}
+]
+"#,
+        nodes,
+        links,
+        inputs,
+        outputs,
+        positions.len(),
+        positions.join(" "),
+        SINK_NODE
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::structs::GraphicEqPoint;
+
+    fn filter(filterType: FilterType, frequency: f32, gain: f32, width: FilterWidth) -> Filter {
+        Filter { filterType, frequency, gain, width, enabled: true }
+    }
+
+    fn channel(speaker: Speaker) -> EqualiserChannelSettings {
+        EqualiserChannelSettings {
+            preamp: -4.5,
+            filters: vec![
+                filter(FilterType::LowShelf, 105., 4.5, FilterWidth::Slope(12.)),
+                filter(FilterType::Peak, 1250., -3., FilterWidth::Q(1.41)),
+                Filter { enabled: false, ..filter(FilterType::Peak, 3000., 6., FilterWidth::Q(4.)) },
+                filter(FilterType::HighPass, 25., 0., FilterWidth::Q(0.5)),
+            ],
+            graphicEq: vec![],
+            ..EqualiserChannelSettings::new(speaker)
+        }
+    }
+
+    // Compares with fixtures/pipewire/`name`, SLYSHMEFX_BLESS=1 rewrites them after an intended change
+    fn golden(name: &str, config: &str) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/pipewire").join(name);
+        if std::env::var_os("SLYSHMEFX_BLESS").is_some() {
+            std::fs::write(&path, config).unwrap();
+        }
+
+        assert_eq!(config, std::fs::read_to_string(&path).unwrap(), "{} differs", name);
+    }
+
+    #[test]
+    fn linkedStereo() {
+        let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![channel(Speaker::FrontLeft), channel(Speaker::FrontRight)]);
+        assert!(settings.isLinked());
+
+        golden("linked.conf", &filterChain(&settings, 48_000.).config());
+    }
+
+    #[test]
+    fn splitStereo() {
+        let mut right = channel(Speaker::FrontRight);
+        right.preamp = -2.;
+        right.filters.truncate(2);
+        let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![channel(Speaker::FrontLeft), right]);
+        assert!(!settings.isLinked());
+
+        golden("split.conf", &filterChain(&settings, 48_000.).config());
+    }
+
+    #[test]
+    fn surroundWithTrims() {
+        let mut settings = EqualiserSettings::fromChannels(ChannelLayout::Surround51, vec![channel(Speaker::FrontLeft)]);
+        settings.linked = true;
+        let mut settings = settings.synchronised();
+        for (i, channel) in settings.channels.iter_mut().enumerate() {
+            channel.trim = -(i as f32);
+            channel.delay = i as f32 * 1.5;
+        }
+
+        golden("surround51.conf", &filterChain(&settings, 48_000.).config());
+    }
+
+    #[test]
+    fn convolutionChannel() {
+        let left = EqualiserChannelSettings { convolution: Some("C:\\IRs\\room left.wav".into()), ..channel(Speaker::FrontLeft) };
+        let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![left, channel(Speaker::FrontRight)]);
+
+        golden("convolution.conf", &filterChain(&settings, 48_000.).config());
+    }
+
+    // dragging a slider only changes controls, enabling a band adds a node
+    #[test]
+    fn topologyIgnoresControlValues() {
+        let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![channel(Speaker::FrontLeft), channel(Speaker::FrontRight)]);
+        let mut louder = settings.clone();
+        for channel in &mut louder.channels {
+            channel.preamp = -8.;
+            channel.filters[1] = filter(FilterType::Peak, 900., 2.5, FilterWidth::Q(0.8));
+        }
+        let mut moreBands = settings.clone();
+        for channel in &mut moreBands.channels {
+            channel.filters[2].enabled = true;
+        }
+
+        let (chain, louderChain) = (filterChain(&settings, 48_000.), filterChain(&louder, 48_000.));
+        assert_eq!(chain.topology(), louderChain.topology());
+        assert_ne!(chain.controls(), louderChain.controls());
+        assert_ne!(chain.topology(), filterChain(&moreBands, 48_000.).topology());
+        assert_ne!(chain.topology(), bypassChain(ChannelLayout::Stereo).topology());
+
+        assert!(louderChain.controls().contains(&("eq2:Freq".into(), 900.)));
+        assert_eq!(
+            propsParam(&[("preamp:Gain".into(), -8.), ("eq2:Q".into(), 0.8)]),
+            "{ params = [ \"preamp:Gain\" -8 \"eq2:Q\" 0.8 ] }"
+        );
+    }
+
+    // the compressor and limiter follow every channel's own chain, a pair of channels per plugin
+    #[test]
+    fn surroundDynamics() {
+        let mut settings = EqualiserSettings::fromChannels(ChannelLayout::Surround51, vec![channel(Speaker::FrontLeft)]);
+        settings.linked = true;
+        let mut settings = settings.synchronised();
+        settings.dynamics.compressor.enabled = true;
+        settings.dynamics.limiter.enabled = true;
+
+        let config = filterChain(&settings, 48_000.).config();
+        assert!(config.contains("{ output = \"fl_fr_compressor:Left output\" input = \"fl_fr_limiter:Input 1\" }"));
+        assert!(config.contains("outputs = [ \"fl_fr_limiter:Output 1\" \"fl_fr_limiter:Output 2\" \"c_lfe_limiter:Output 1\""));
+        golden("dynamics51.conf", &config);
+    }
+
+    // a flat curve needs no filters, its level ends up in the preamp
+    #[test]
+    fn graphicEqLevelReachesThePreamp() {
+        let flat = EqualiserChannelSettings {
+            preamp: 0.,
+            filters: vec![],
+            graphicEq: vec![GraphicEqPoint { frequency: 20., gain: -6. }, GraphicEqPoint { frequency: 20_000., gain: -6. }],
+            ..EqualiserChannelSettings::new(Speaker::FrontLeft)
+        };
+        let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![flat.clone(), EqualiserChannelSettings { speaker: Speaker::FrontRight, ..flat }]);
+
+        let controls = filterChain(&settings, 48_000.).controls();
+        let preamps: Vec<f32> = controls.iter().filter(|(name, _)| name.ends_with("preamp:Gain")).map(|(_, gain)| *gain).collect();
+        assert!(!preamps.is_empty() && preamps.iter().all(|&gain| gain == -6.), "{:?}", controls);
+        assert_eq!(controls.len(), 3, "{:?}", controls);
+    }
+
+    #[test]
+    fn clampedDynamicsAreReported() {
+        let mut dynamics = DynamicsSettings::default();
+        assert!(clampedDynamics(&dynamics).is_empty());
+
+        dynamics.compressor = Compressor { threshold: -40., ..Compressor::default() };
+        dynamics.limiter = Limiter { enabled: true, release: 5., ..Limiter::default() };
+        assert_eq!(clampedDynamics(&dynamics), vec!["Limiter release 5 ms is limited to 10 ms"]);
+
+        dynamics.compressor.enabled = true;
+        assert_eq!(clampedDynamics(&dynamics), vec!["Compressor threshold -40 dB is limited to -30 dB", "Limiter release 5 ms is limited to 10 ms"]);
+    }
+}
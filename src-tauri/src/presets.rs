@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+
+use crate::structs::{ChannelLayout, EqualiserChannelSettings, EqualiserPreset, EqualiserSettings, Filter, FilterType, FilterWidth, Speaker};
+
+
+
+const PRESETS_FILE: &str = "presets.json";
+
+pub fn builtInPresets() -> Vec<EqualiserPreset> {
+    let preset = |name: &str, filters: &[(FilterType, f32, f32, f32)]| {
+        let channel = |speaker| EqualiserChannelSettings {
+            autoHeadroom: true,
+            filters: filters
+                .iter()
+                .map(|&(filterType, frequency, gain, q)| Filter {
+                    filterType,
+                    frequency,
+                    gain,
+                    width: FilterWidth::Q(q),
+                    enabled: true,
+                })
+                .collect(),
+            ..EqualiserChannelSettings::new(speaker)
+        };
+
+        // the headroom depends on the rate they run at, `allPresets` works it out
+        EqualiserPreset {
+            name: name.into(),
+            settings: EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![channel(Speaker::FrontLeft), channel(Speaker::FrontRight)]),
+            builtIn: true,
+        }
+    };
+
+    vec![
+        EqualiserPreset {
+            name: "Flat".into(),
+            settings: EqualiserSettings::default(),
+            builtIn: true,
+        },
+        preset("Bass boost", &[(FilterType::LowShelf, 105.0, 6.0, 0.7), (FilterType::Peak, 60.0, 2.0, 1.0)]),
+        preset("Treble boost", &[(FilterType::HighShelf, 6000.0, 5.0, 0.7)]),
+        preset("Vocal", &[
+            (FilterType::HighPass, 80.0, 0.0, 0.7),
+            (FilterType::Peak, 250.0, -2.0, 1.0),
+            (FilterType::Peak, 2500.0, 3.0, 1.0),
+            (FilterType::Peak, 5000.0, 2.0, 1.5),
+        ]),
+        preset("V-shape", &[
+            (FilterType::LowShelf, 105.0, 5.0, 0.7),
+            (FilterType::Peak, 1000.0, -3.0, 0.8),
+            (FilterType::HighShelf, 8000.0, 4.0, 0.7),
+        ]),
+        // roughly the Harman over-ear target's bass shelf and upper mid lift relative to a diffuse field tuned headphone
+        preset("Headphones (Harman)", &[
+            (FilterType::LowShelf, 105.0, 6.0, 0.7),
+            (FilterType::Peak, 3000.0, 2.0, 1.2),
+            (FilterType::HighShelf, 10000.0, -2.0, 0.7),
+        ]),
+    ]
+}
+
+fn presetsPath(dir: &Path) -> PathBuf {
+    dir.join(PRESETS_FILE)
+}
+
+// Only user presets are stored, built in ones always come from the code
+pub fn loadUserPresets(dir: &Path) -> Result<Vec<EqualiserPreset>, Box<dyn std::error::Error>> {
+    let path = presetsPath(dir);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut presets: Vec<EqualiserPreset> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    presets.iter_mut().for_each(|p| p.builtIn = false);
+    Ok(presets)
+}
+
+pub fn saveUserPresets(dir: &Path, presets: &[EqualiserPreset]) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(presetsPath(dir), serde_json::to_string_pretty(presets)?)?;
+
+    Ok(())
+}
+
+// built in presets with their headroom for `sampleRate`
+pub fn allPresets(dir: &Path, sampleRate: f64) -> Result<Vec<EqualiserPreset>, Box<dyn std::error::Error>> {
+    let mut presets: Vec<EqualiserPreset> = builtInPresets()
+        .into_iter()
+        .map(|p| EqualiserPreset { settings: p.settings.withAutoHeadroom(sampleRate), ..p })
+        .collect();
+    presets.extend(loadUserPresets(dir)?);
+
+    Ok(presets)
+}
+
+pub fn findPreset(dir: &Path, name: &str, sampleRate: f64) -> Result<EqualiserPreset, Box<dyn std::error::Error>> {
+    allPresets(dir, sampleRate)?
+        .into_iter()
+        .find(|p| p.name == name)
+        .ok_or_else(|| format!("Preset {} not found", name).into())
+}
+
+// `name`, or with the first " (n)" suffix no preset has yet, so adding one never silently replaces another
+pub fn unusedName(dir: &Path, name: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let existing: Vec<EqualiserPreset> = builtInPresets().into_iter().chain(loadUserPresets(dir)?).collect();
+    let baseName = name.trim();
+    let mut name = baseName.to_string();
+    let mut suffix = 1;
+    while existing.iter().any(|p| p.name == name) {
+        suffix += 1;
+        name = format!("{} ({})", baseName, suffix);
+    }
+
+    Ok(name)
+}
+
+// Adds or replaces a user preset, built in names are reserved
+pub fn storePreset(dir: &Path, preset: EqualiserPreset) -> Result<(), Box<dyn std::error::Error>> {
+    let name = preset.name.trim().to_string();
+    if name.is_empty() {
+        return Err("Preset name can't be empty".into());
+    }
+    if builtInPresets().iter().any(|p| p.name == name) {
+        return Err(format!("{} is a built in preset", name).into());
+    }
+
+    let mut presets = loadUserPresets(dir)?;
+    let preset = EqualiserPreset { name, builtIn: false, ..preset };
+
+    match presets.iter_mut().find(|p| p.name == preset.name) {
+        Some(existing) => *existing = preset,
+        None => presets.push(preset),
+    }
+
+    saveUserPresets(dir, &presets)
+}
+
+pub fn renamePreset(dir: &Path, name: &str, newName: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut presets = loadUserPresets(dir)?;
+    let newName = newName.trim();
+
+    if newName.is_empty() {
+        return Err("Preset name can't be empty".into());
+    }
+    if builtInPresets().iter().chain(presets.iter()).any(|p| p.name == newName) {
+        return Err(format!("A preset called {} already exists", newName).into());
+    }
+
+    let preset = presets
+        .iter_mut()
+        .find(|p| p.name == name)
+        .ok_or_else(|| format!("Preset {} not found or built in", name))?;
+    preset.name = newName.into();
+
+    saveUserPresets(dir, &presets)
+}
+
+pub fn deletePreset(dir: &Path, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut presets = loadUserPresets(dir)?;
+    let count = presets.len();
+
+    presets.retain(|p| p.name != name);
+    if presets.len() == count {
+        return Err(format!("Preset {} not found or built in", name).into());
+    }
+
+    saveUserPresets(dir, &presets)
+}
@@ -0,0 +1,221 @@
+use std::{thread, time::Duration};
+
+use tauri::{AppHandle, Emitter};
+
+use crate::{backend::updateOverrides, structs::{AppProfile, AppProfileSettings, VisualiserSettings}, util::foregroundExecutable};
+
+
+
+// Tells which application the profiles should follow, swapped for a scripted one when testing the switching
+pub trait ApplicationWatcher {
+    // executable file name, None when it can't be told and the current profile should stay
+    fn activeApplication(&mut self) -> Option<String>;
+}
+
+// Follows the application in front, the one the user is listening to in practically every case
+pub struct ForegroundWatcher;
+impl ApplicationWatcher for ForegroundWatcher {
+    fn activeApplication(&mut self) -> Option<String> {
+        foregroundExecutable()
+    }
+}
+
+fn executableName(name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    name.strip_suffix(".exe").map(str::to_string).unwrap_or(name)
+}
+
+// The first rule matching the executable, or the fallback profile
+pub fn matchingProfile<'a>(settings: &'a AppProfileSettings, executable: &str) -> Option<&'a AppProfile> {
+    let executable = executableName(executable);
+
+    settings
+        .rules
+        .iter()
+        .find(|rule| executableName(&rule.executable) == executable)
+        .or(settings.fallback.as_ref())
+}
+
+// Shows a profile's look, remembering the user's own settings so they can be put back
+fn applyVisualiserSettings(appHandle: &AppHandle, settings: Option<&VisualiserSettings>) {
+    let mut base = crate::PROFILE_BASE_VISUALISER.write().unwrap();
+    let mut current = crate::VISUALISER_CONFIG.write().unwrap();
+
+    let next = match (settings, base.take()) {
+        (Some(settings), previous) => {
+            let own = previous.unwrap_or_else(|| current.clone());
+            // the screen and the device delays describe the setup rather than the look
+            let next = VisualiserSettings {
+                screen: own.screen.clone(),
+                latencyCompensation: own.latencyCompensation.clone(),
+                ..settings.clone()
+            };
+
+            *base = Some(own);
+            next
+        },
+        (None, Some(own)) => own,
+        (None, None) => return,
+    };
+
+    *current = next.clone();
+    let _ = appHandle.emit("visualiserUpdate", serde_json::to_string(&next).unwrap());
+}
+
+fn applyProfile(appHandle: &AppHandle, profile: Option<&AppProfile>) {
+    updateOverrides(appHandle, |overrides| overrides.profile = profile.and_then(|p| p.preset.clone()));
+    applyVisualiserSettings(appHandle, profile.and_then(|p| p.visualiserSettings.as_ref()));
+}
+
+#[derive(Debug)]
+pub enum ProfileChange<'a> {
+    Keep,
+    // None puts the user's own settings back
+    Switch(Option<&'a AppProfile>),
+}
+
+// Decides when the profile changes, kept apart from applying it so the switching can be tested
+#[derive(Default)]
+pub struct ProfileTracker {
+    // serialised so edits to the active rule are picked up too
+    lastProfile: Option<String>,
+}
+impl ProfileTracker {
+    // `executable` is None when the application can't be told, only looked at while profiles are enabled
+    pub fn update<'a>(&mut self, settings: &'a AppProfileSettings, executable: Option<&str>) -> ProfileChange<'a> {
+        if !settings.enabled {
+            return match self.lastProfile.take() {
+                Some(_) => ProfileChange::Switch(None),
+                None => ProfileChange::Keep,
+            };
+        }
+
+        let Some(executable) = executable else {
+            return ProfileChange::Keep;
+        };
+
+        let profile = matchingProfile(settings, executable);
+        let key = serde_json::to_string(&profile).unwrap();
+        if self.lastProfile.as_ref() == Some(&key) {
+            return ProfileChange::Keep;
+        }
+
+        self.lastProfile = Some(key);
+        ProfileChange::Switch(profile)
+    }
+}
+
+pub fn watchApplications(appHandle: AppHandle, mut watcher: impl ApplicationWatcher) {
+    let mut tracker = ProfileTracker::default();
+
+    loop {
+        thread::sleep(Duration::from_secs(1));
+
+        let settings = crate::APP_PROFILE_CONFIG.read().unwrap().clone();
+        let executable = if settings.enabled { watcher.activeApplication() } else { None };
+
+        if let ProfileChange::Switch(profile) = tracker.update(&settings, executable.as_deref()) {
+            applyProfile(&appHandle, profile);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // plays back a list of foreground applications, one per poll
+    struct ScriptedWatcher(std::vec::IntoIter<Option<&'static str>>);
+    impl ApplicationWatcher for ScriptedWatcher {
+        fn activeApplication(&mut self) -> Option<String> {
+            self.0.next().flatten().map(str::to_string)
+        }
+    }
+
+    fn profile(executable: &str, preset: &str) -> AppProfile {
+        AppProfile { executable: executable.into(), preset: Some(preset.into()), visualiserSettings: None }
+    }
+
+    fn settings() -> AppProfileSettings {
+        AppProfileSettings {
+            enabled: true,
+            rules: vec![profile("Spotify.exe", "music"), profile("game", "game"), profile("spotify", "shadowed")],
+            fallback: None,
+        }
+    }
+
+    fn preset(change: ProfileChange) -> Option<Option<String>> {
+        match change {
+            ProfileChange::Keep => None,
+            ProfileChange::Switch(profile) => Some(profile.and_then(|p| p.preset.clone())),
+        }
+    }
+
+    #[test]
+    fn matching() {
+        let mut settings = settings();
+
+        assert_eq!(matchingProfile(&settings, "spotify").unwrap().preset.as_deref(), Some("music"));
+        assert_eq!(matchingProfile(&settings, " SPOTIFY.EXE ").unwrap().preset.as_deref(), Some("music"));
+        assert_eq!(matchingProfile(&settings, "Game.exe").unwrap().preset.as_deref(), Some("game"));
+        assert!(matchingProfile(&settings, "explorer.exe").is_none());
+
+        settings.fallback = Some(profile("", "fallback"));
+        assert_eq!(matchingProfile(&settings, "explorer.exe").unwrap().preset.as_deref(), Some("fallback"));
+        assert_eq!(matchingProfile(&settings, "game").unwrap().preset.as_deref(), Some("game"));
+    }
+
+    #[test]
+    fn switching() {
+        let settings = settings();
+        let mut watcher = ScriptedWatcher(
+            vec![Some("spotify.exe"), Some("Spotify.exe"), None, Some("explorer.exe"), Some("explorer.exe"), Some("game.exe")].into_iter(),
+        );
+        let mut tracker = ProfileTracker::default();
+
+        let changes: Vec<Option<Option<String>>> =
+            (0..6).map(|_| preset(tracker.update(&settings, watcher.activeApplication().as_deref()))).collect();
+        assert_eq!(
+            changes,
+            vec![
+                Some(Some("music".into())),
+                None,
+                // unknown applications keep the profile
+                None,
+                Some(None),
+                None,
+                Some(Some("game".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn editsToTheActiveRuleApply() {
+        let mut settings = settings();
+        let mut tracker = ProfileTracker::default();
+
+        assert_eq!(preset(tracker.update(&settings, Some("game"))), Some(Some("game".into())));
+        settings.rules[1].preset = Some("louder".into());
+        assert_eq!(preset(tracker.update(&settings, Some("game"))), Some(Some("louder".into())));
+    }
+
+    #[test]
+    fn disablingRestores() {
+        let mut settings = settings();
+        let mut tracker = ProfileTracker::default();
+
+        settings.enabled = false;
+        assert_eq!(preset(tracker.update(&settings, None)), None);
+
+        settings.enabled = true;
+        assert_eq!(preset(tracker.update(&settings, Some("spotify"))), Some(Some("music".into())));
+        settings.enabled = false;
+        assert_eq!(preset(tracker.update(&settings, None)), Some(None));
+        assert_eq!(preset(tracker.update(&settings, None)), None);
+
+        // comes back when enabled again
+        settings.enabled = true;
+        assert_eq!(preset(tracker.update(&settings, Some("spotify"))), Some(Some("music".into())));
+    }
+}
@@ -0,0 +1,322 @@
+use std::f64::consts::PI;
+
+use crate::{convolution, structs::{EqualiserChannelSettings, EqualiserResponse, EqualiserSettings, Filter, FilterType, FilterWidth, GraphicEqPoint, ResponsePoint}};
+
+
+
+// Normalised biquad coefficients (a0 == 1), computed with the RBJ audio EQ cookbook formulas that Equalizer APO uses
+#[derive(Clone, Copy, Debug)]
+pub struct Biquad {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+}
+impl Biquad {
+    pub const IDENTITY: Self = Self { b0: 1., b1: 0., b2: 0., a1: 0., a2: 0. };
+
+    pub fn fromFilter(filter: &Filter, sampleRate: f64) -> Self {
+        let frequency = filter.frequency as f64;
+        if !filter.enabled || frequency <= 0. || frequency >= sampleRate / 2. {
+            return Self::IDENTITY;
+        }
+
+        let a = 10f64.powf(filter.gain as f64 / 40.);
+        let w0 = 2. * PI * frequency / sampleRate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = match filter.width {
+            FilterWidth::Q(q) => sin / (2. * (q as f64).max(1e-3)),
+            // APO's shelf slope is given in dB per octave, 12 dB being the cookbook's S = 1
+            FilterWidth::Slope(slope) => {
+                let s = (slope as f64 / 12.).max(1e-3);
+                sin / 2. * ((a + 1. / a) * (1. / s - 1.) + 2.).max(0.).sqrt()
+            },
+        };
+        let sqrtA2Alpha = 2. * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match filter.filterType {
+            FilterType::Peak => (1. + alpha * a, -2. * cos, 1. - alpha * a, 1. + alpha / a, -2. * cos, 1. - alpha / a),
+            FilterType::LowShelf => (
+                a * ((a + 1.) - (a - 1.) * cos + sqrtA2Alpha),
+                2. * a * ((a - 1.) - (a + 1.) * cos),
+                a * ((a + 1.) - (a - 1.) * cos - sqrtA2Alpha),
+                (a + 1.) + (a - 1.) * cos + sqrtA2Alpha,
+                -2. * ((a - 1.) + (a + 1.) * cos),
+                (a + 1.) + (a - 1.) * cos - sqrtA2Alpha,
+            ),
+            FilterType::HighShelf => (
+                a * ((a + 1.) + (a - 1.) * cos + sqrtA2Alpha),
+                -2. * a * ((a - 1.) + (a + 1.) * cos),
+                a * ((a + 1.) + (a - 1.) * cos - sqrtA2Alpha),
+                (a + 1.) - (a - 1.) * cos + sqrtA2Alpha,
+                2. * ((a - 1.) - (a + 1.) * cos),
+                (a + 1.) - (a - 1.) * cos - sqrtA2Alpha,
+            ),
+            FilterType::LowPass => ((1. - cos) / 2., 1. - cos, (1. - cos) / 2., 1. + alpha, -2. * cos, 1. - alpha),
+            FilterType::HighPass => ((1. + cos) / 2., -(1. + cos), (1. + cos) / 2., 1. + alpha, -2. * cos, 1. - alpha),
+            FilterType::BandPass => (alpha, 0., -alpha, 1. + alpha, -2. * cos, 1. - alpha),
+            FilterType::Notch => (1., -2. * cos, 1., 1. + alpha, -2. * cos, 1. - alpha),
+            FilterType::AllPass => (1. - alpha, -2. * cos, 1. + alpha, 1. + alpha, -2. * cos, 1. - alpha),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    // complex response at `frequency`, as (re, im)
+    pub fn evaluate(&self, frequency: f64, sampleRate: f64) -> (f64, f64) {
+        let w = 2. * PI * frequency / sampleRate;
+        let (sin1, cos1) = w.sin_cos();
+        let (sin2, cos2) = (2. * w).sin_cos();
+
+        // z^-1 = cos(w) - j sin(w)
+        let numerator = (self.b0 + self.b1 * cos1 + self.b2 * cos2, -self.b1 * sin1 - self.b2 * sin2);
+        let denominator = (1. + self.a1 * cos1 + self.a2 * cos2, -self.a1 * sin1 - self.a2 * sin2);
+        let norm = denominator.0 * denominator.0 + denominator.1 * denominator.1;
+
+        (
+            (numerator.0 * denominator.0 + numerator.1 * denominator.1) / norm,
+            (numerator.1 * denominator.0 - numerator.0 * denominator.1) / norm,
+        )
+    }
+
+    pub fn magnitudeDb(&self, frequency: f64, sampleRate: f64) -> f64 {
+        let (re, im) = self.evaluate(frequency, sampleRate);
+        10. * (re * re + im * im).max(1e-30).log10()
+    }
+}
+
+// The Q the cookbook's shelf slope works out to at the given gain, for targets whose shelves only take a Q
+pub fn slopeToQ(slope: f32, gain: f32) -> f32 {
+    let a = 10f32.powf(gain / 40.);
+    let s = (slope / 12.).max(1e-3);
+
+    1. / ((a + 1. / a) * (1. / s - 1.) + 2.).max(1e-6).sqrt()
+}
+
+pub fn logFrequencies(points: usize, sampleRate: f64) -> Vec<f64> {
+    let (min, max) = (20f64, 20_000f64.min(sampleRate / 2. * 0.999));
+    if points < 2 {
+        return vec![min; points];
+    }
+
+    (0..points)
+        .map(|i| min * (max / min).powf(i as f64 / (points - 1) as f64))
+        .collect()
+}
+
+// Gain of a graphic EQ curve in dB, linear between points on a log frequency axis and held flat past either end
+pub fn graphicEqGain(points: &[GraphicEqPoint], frequency: f64) -> f64 {
+    let Some(first) = points.first() else {
+        return 0.;
+    };
+    if frequency <= first.frequency as f64 {
+        return first.gain as f64;
+    }
+
+    for pair in points.windows(2) {
+        let (low, high) = (pair[0].frequency as f64, pair[1].frequency as f64);
+        if frequency <= high {
+            if high <= low {
+                return pair[1].gain as f64;
+            }
+
+            let position = (frequency / low).ln() / (high / low).ln();
+            return pair[0].gain as f64 + (pair[1].gain - pair[0].gain) as f64 * position;
+        }
+    }
+
+    points.last().unwrap().gain as f64
+}
+
+// Complex response of a channel's impulse response, identity without one or when it can't be loaded
+fn convolutionResponse(channel: &EqualiserChannelSettings, frequencies: &[f64]) -> Vec<(f64, f64)> {
+    match channel.convolution.as_ref().map(|path| convolution::load(std::path::Path::new(path))) {
+        Some(Ok(ir)) => ir.frequencyResponse(channel.speaker.irChannel(ir.channels.len()), frequencies),
+        _ => vec![(1., 0.); frequencies.len()],
+    }
+}
+
+// Combined response of a channel's filters, graphic EQ, impulse response, preamp and trim, magnitudes in dB and phases in degrees.
+// The delay is left out, it would only wrap the phase around.
+pub fn channelResponse(channel: &EqualiserChannelSettings, frequencies: &[f64], sampleRate: f64) -> Vec<ResponsePoint> {
+    let biquads: Vec<Biquad> = channel.filters.iter().map(|f| Biquad::fromFilter(f, sampleRate)).collect();
+    let convolution = convolutionResponse(channel, frequencies);
+
+    frequencies
+        .iter()
+        .zip(convolution)
+        .map(|(&frequency, (irRe, irIm))| {
+            // APO's graphic EQ is linear phase, so it only adds to the magnitude
+            let base = (
+                (channel.preamp + channel.trim) as f64 + graphicEqGain(&channel.graphicEq, frequency) + 10. * (irRe * irRe + irIm * irIm).max(1e-30).log10(),
+                irIm.atan2(irRe),
+            );
+            let (magnitude, phase) = biquads.iter().fold(base, |(magnitude, phase), biquad| {
+                let (re, im) = biquad.evaluate(frequency, sampleRate);
+                (magnitude + 10. * (re * re + im * im).max(1e-30).log10(), phase + im.atan2(re))
+            });
+
+            ResponsePoint {
+                frequency: frequency as f32,
+                magnitude: magnitude as f32,
+                // wrapped into -180..180 like measurement software shows it
+                phase: (phase.to_degrees() + 180.).rem_euclid(360.) as f32 - 180.,
+            }
+        })
+        .collect()
+}
+
+// Response of what the backend runs, loudness compensation included
+pub fn settingsResponse(settings: &EqualiserSettings, points: usize, sampleRate: f64) -> EqualiserResponse {
+    let frequencies = logFrequencies(points, sampleRate);
+    let settings = settings.compensated(sampleRate);
+
+    EqualiserResponse {
+        channels: settings.channels.iter().map(|c| (c.speaker, channelResponse(c, &frequencies, sampleRate))).collect(),
+    }
+}
+
+// Highest combined boost of a channel's filters, graphic EQ and impulse response in dB, never below 0,
+// checked on a dense grid plus every filter's and graphic EQ point's own frequency
+pub fn maxBoost(channel: &EqualiserChannelSettings, sampleRate: f64) -> f64 {
+    let biquads: Vec<Biquad> = channel.filters.iter().map(|f| Biquad::fromFilter(f, sampleRate)).collect();
+    let mut frequencies = logFrequencies(512, sampleRate);
+    frequencies.extend(
+        channel.filters.iter().map(|f| f.frequency as f64)
+            .chain(channel.graphicEq.iter().map(|p| p.frequency as f64))
+            .filter(|&f| f > 0. && f < sampleRate / 2.),
+    );
+
+    let convolution = convolutionResponse(channel, &frequencies);
+
+    frequencies
+        .iter()
+        .zip(convolution)
+        .map(|(&frequency, (irRe, irIm))| {
+            graphicEqGain(&channel.graphicEq, frequency)
+                + 10. * (irRe * irRe + irIm * irIm).max(1e-30).log10()
+                + biquads.iter().map(|b| b.magnitudeDb(frequency, sampleRate)).sum::<f64>()
+        })
+        .fold(0., f64::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATES: [f64; 3] = [44_100., 48_000., 96_000.];
+
+    fn biquad(filterType: FilterType, frequency: f32, gain: f32, width: FilterWidth, sampleRate: f64) -> Biquad {
+        Biquad::fromFilter(&Filter { filterType, frequency, gain, width, enabled: true }, sampleRate)
+    }
+
+    fn assertNear(actual: f64, expected: f64, tolerance: f64, context: &str) {
+        assert!((actual - expected).abs() <= tolerance, "{}: {} dB, expected {} dB", context, actual, expected);
+    }
+
+    #[test]
+    fn peakGainAtCentre() {
+        for sampleRate in SAMPLE_RATES {
+            for (frequency, gain, q) in [(100., 6., 1.), (1000., -12., 4.), (8000., 3.5, 0.5)] {
+                let peak = biquad(FilterType::Peak, frequency, gain, FilterWidth::Q(q), sampleRate);
+                let context = format!("{} Hz peak at {} Hz", frequency, sampleRate);
+
+                assertNear(peak.magnitudeDb(frequency as f64, sampleRate), gain as f64, 1e-9, &context);
+                assertNear(peak.magnitudeDb(0., sampleRate), 0., 1e-9, &context);
+                assertNear(peak.magnitudeDb(sampleRate / 2., sampleRate), 0., 1e-9, &context);
+            }
+        }
+    }
+
+    // the full gain on one side, none on the other and half of it at the corner
+    #[test]
+    fn shelfAsymptotes() {
+        for sampleRate in SAMPLE_RATES {
+            for width in [FilterWidth::Q(0.707), FilterWidth::Q(1.2), FilterWidth::Slope(6.), FilterWidth::Slope(12.)] {
+                for gain in [-9f32, 4.5] {
+                    let context = format!("{:?} {} dB shelf at {} Hz", width, gain, sampleRate);
+
+                    let low = biquad(FilterType::LowShelf, 200., gain, width, sampleRate);
+                    assertNear(low.magnitudeDb(0., sampleRate), gain as f64, 1e-9, &context);
+                    assertNear(low.magnitudeDb(sampleRate / 2., sampleRate), 0., 1e-9, &context);
+                    assertNear(low.magnitudeDb(200., sampleRate), gain as f64 / 2., 1e-9, &context);
+
+                    let high = biquad(FilterType::HighShelf, 5000., gain, width, sampleRate);
+                    assertNear(high.magnitudeDb(0., sampleRate), 0., 1e-9, &context);
+                    assertNear(high.magnitudeDb(sampleRate / 2., sampleRate), gain as f64, 1e-9, &context);
+                    assertNear(high.magnitudeDb(5000., sampleRate), gain as f64 / 2., 1e-9, &context);
+                }
+            }
+        }
+    }
+
+    // the cookbook's passes are Q at the cutoff, which is the familiar -3 dB for a Butterworth Q
+    #[test]
+    fn passesAtCutoff() {
+        for sampleRate in SAMPLE_RATES {
+            for q in [0.5f32, std::f32::consts::FRAC_1_SQRT_2, 2.] {
+                let context = format!("Q {} at {} Hz", q, sampleRate);
+                let expected = 20. * (q as f64).log10();
+
+                let lowPass = biquad(FilterType::LowPass, 1000., 0., FilterWidth::Q(q), sampleRate);
+                assertNear(lowPass.magnitudeDb(1000., sampleRate), expected, 1e-6, &context);
+                assertNear(lowPass.magnitudeDb(0., sampleRate), 0., 1e-9, &context);
+
+                let highPass = biquad(FilterType::HighPass, 1000., 0., FilterWidth::Q(q), sampleRate);
+                assertNear(highPass.magnitudeDb(1000., sampleRate), expected, 1e-6, &context);
+                assertNear(highPass.magnitudeDb(sampleRate / 2., sampleRate), 0., 1e-9, &context);
+            }
+
+            let butterworth = biquad(FilterType::LowPass, 1000., 0., FilterWidth::Q(std::f32::consts::FRAC_1_SQRT_2), sampleRate);
+            assertNear(butterworth.magnitudeDb(1000., sampleRate), -3.0103, 1e-3, "Butterworth low pass");
+        }
+    }
+
+    #[test]
+    fn bandPassNotchAndAllPass() {
+        for sampleRate in SAMPLE_RATES {
+            let bandPass = biquad(FilterType::BandPass, 1000., 0., FilterWidth::Q(2.), sampleRate);
+            assertNear(bandPass.magnitudeDb(1000., sampleRate), 0., 1e-9, "band pass");
+
+            let notch = biquad(FilterType::Notch, 1000., 0., FilterWidth::Q(30.), sampleRate);
+            assert!(notch.magnitudeDb(1000., sampleRate) < -100., "the notch has to null its centre");
+            assertNear(notch.magnitudeDb(100., sampleRate), 0., 1e-3, "notch");
+
+            let allPass = biquad(FilterType::AllPass, 1000., 0., FilterWidth::Q(0.707), sampleRate);
+            for frequency in [20., 1000., 15_000.] {
+                assertNear(allPass.magnitudeDb(frequency, sampleRate), 0., 1e-9, "all pass");
+            }
+        }
+    }
+
+    // S = 1 is the steepest shelf without overshoot, its Q is 1/sqrt(2) whatever the gain
+    #[test]
+    fn shelfSlopes() {
+        for gain in [-12f32, 3., 15.] {
+            assertNear(slopeToQ(12., gain) as f64, std::f64::consts::FRAC_1_SQRT_2, 1e-6, "slope to Q");
+
+            let slope = biquad(FilterType::LowShelf, 300., gain, FilterWidth::Slope(12.), 48_000.);
+            let q = biquad(FilterType::LowShelf, 300., gain, FilterWidth::Q(slopeToQ(12., gain)), 48_000.);
+            for frequency in [50., 300., 1000.] {
+                assertNear(slope.magnitudeDb(frequency, 48_000.), q.magnitudeDb(frequency, 48_000.), 1e-5, "slope against Q");
+            }
+        }
+    }
+
+    #[test]
+    fn identityWhenDisabledOrPastNyquist() {
+        let disabled = Biquad::fromFilter(&Filter { filterType: FilterType::Peak, frequency: 1000., gain: 6., width: FilterWidth::Q(1.), enabled: false }, 48_000.);
+        let pastNyquist = biquad(FilterType::Peak, 30_000., 6., FilterWidth::Q(1.), 48_000.);
+
+        for biquad in [disabled, pastNyquist] {
+            assertNear(biquad.magnitudeDb(1000., 48_000.), 0., 1e-12, "identity");
+        }
+    }
+}
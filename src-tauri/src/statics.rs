@@ -1,31 +1,57 @@
-use std::sync::RwLock;
-use crate::structs::{EqualiserChannelSettings, EqualiserSettings, VisualiserSettings, VisualiserType};
+use std::{collections::BTreeMap, sync::{LazyLock, Mutex, RwLock}};
+use crate::{backend::DiscoveredBackend, structs::{AppProfileSettings, BandAggregation, ComparisonSlot, EqualiserBackendSettings, EqualiserComparison, EqualiserOverrides, EqualiserSettings, PipeWireFilter, SpatialSmoothing, VisualiserSettings, VisualiserType}};
 
 
 
 pub static IS_CAPTURE_RUNNING: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
 pub static IS_ATTACHED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
 
-pub static EQUALISER_CONFIG: RwLock<EqualiserSettings> = RwLock::new(EqualiserSettings(
-    EqualiserChannelSettings {
-        preamp: 0.0,
-        channelLeft: true,
-        bassGain: 0.0,
-        lowGain: 0.0,
-        midGain: 0.0,
-        highGain: 0.0,
-        trebleGain: 0.0,
-    }, 
-    EqualiserChannelSettings {
-        preamp: 0.0,
-        channelLeft: false,
-        bassGain: 0.0,
-        lowGain: 0.0,
-        midGain: 0.0,
-        highGain: 0.0,
-        trebleGain: 0.0,
-    }
-));
+// the default output device's id, kept current by backend::watchOutputDevice so the capture loop needn't ask WASAPI
+pub static OUTPUT_DEVICE: RwLock<Option<String>> = RwLock::new(None);
+
+// the default stereo channels until the saved settings are loaded
+pub static EQUALISER_CONFIG: LazyLock<RwLock<EqualiserSettings>> = LazyLock::new(|| RwLock::new(EqualiserSettings::default()));
+
+pub static EQUALISER_BACKEND: Mutex<Option<DiscoveredBackend>> = Mutex::new(None);
+
+pub static EQUALISER_BACKEND_CONFIG: RwLock<EqualiserBackendSettings> = RwLock::new(EqualiserBackendSettings {
+    configDirOverride: None,
+    deviceBindings: BTreeMap::new(),
+});
+
+// held from reading the comparison to storing what was switched to, so applies, bypasses and A/B switches can't interleave
+pub static EQUALISER_SWITCH: Mutex<()> = Mutex::new(());
+
+pub static EQUALISER_COMPARISON: RwLock<EqualiserComparison> = RwLock::new(EqualiserComparison {
+    bypassed: false,
+    slot: ComparisonSlot::A,
+    stored: None,
+});
+
+pub static APP_PROFILE_CONFIG: RwLock<AppProfileSettings> = RwLock::new(AppProfileSettings {
+    enabled: false,
+    rules: Vec::new(),
+    fallback: None,
+});
+
+// the PipeWire client running the equaliser's filter-chain on Linux
+pub static PIPEWIRE_FILTER: Mutex<PipeWireFilter> = Mutex::new(PipeWireFilter {
+    process: None,
+    topology: None,
+    restarts: 0,
+    restarting: false,
+});
+
+// the user's own equaliser settings while the output device's or an application profile's preset is applied
+pub static BASE_EQUALISER: RwLock<Option<EqualiserSettings>> = RwLock::new(None);
+
+pub static EQUALISER_OVERRIDES: RwLock<EqualiserOverrides> = RwLock::new(EqualiserOverrides {
+    device: None,
+    profile: None,
+});
+
+// the user's own visualiser settings while an application profile's look is shown
+pub static PROFILE_BASE_VISUALISER: RwLock<Option<VisualiserSettings>> = RwLock::new(None);
 
 pub static VISUALISER_CONFIG: RwLock<VisualiserSettings> = RwLock::new(VisualiserSettings {
     barsColour: (0, 0, 0, 170),
@@ -33,4 +59,18 @@ pub static VISUALISER_CONFIG: RwLock<VisualiserSettings> = RwLock::new(Visualise
     useDesktopBackground: true,
     resolution: 128,
     screen: None,
+    targetFps: 60,
+    throttledFps: 15,
+    adaptiveFrameRate: true,
+    silenceThreshold: -70.0,
+    silenceTimeout: 5.0,
+    minFrequency: 75.0,
+    maxFrequency: 20_000.0,
+    bandEdges: Vec::new(),
+    bandAggregation: BandAggregation::Mean,
+    spatialSmoothing: SpatialSmoothing::None,
+    smoothingRadius: 3,
+    smoothingSigma: 1.5,
+    monstercatFalloff: 1.5,
+    latencyCompensation: BTreeMap::new(),
 });
@@ -1,21 +1,60 @@
+use std::collections::BTreeMap;
 use tauri::Manager;
 
+use crate::{loudness::loudnessFilters, response::maxBoost};
 
 
+
+// auto headroom under loudness compensation is recomputed until it moves less than this many dB, or gives up
+const HEADROOM_TOLERANCE: f32 = 0.1;
+const MAX_HEADROOM_ROUNDS: usize = 10;
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct AppConfig {
     pub visualiserSettings: VisualiserSettings,
     pub equaliserSettings: EqualiserSettings,
+    #[serde(default)]
+    pub equaliserBackendSettings: EqualiserBackendSettings,
+    #[serde(default)]
+    pub appProfileSettings: AppProfileSettings,
+    #[serde(default)]
+    pub equaliserComparison: EqualiserComparison,
 }
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             visualiserSettings: VisualiserSettings::default(),
             equaliserSettings: EqualiserSettings::default(),
+            equaliserBackendSettings: EqualiserBackendSettings::default(),
+            appProfileSettings: AppProfileSettings::default(),
+            equaliserComparison: EqualiserComparison::default(),
         }
     }
 }
 impl AppConfig {
+    // snapshot of the live settings, used whenever one of them changes.
+    // While an application profile is active its preset and look aren't saved, the user's own settings are.
+    pub fn current() -> Self {
+        let visualiserSettings = crate::PROFILE_BASE_VISUALISER
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| crate::VISUALISER_CONFIG.read().unwrap().clone());
+        let equaliserSettings = crate::BASE_EQUALISER
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| crate::EQUALISER_CONFIG.read().unwrap().clone());
+
+        Self {
+            visualiserSettings,
+            equaliserSettings,
+            equaliserBackendSettings: crate::EQUALISER_BACKEND_CONFIG.read().unwrap().clone(),
+            appProfileSettings: crate::APP_PROFILE_CONFIG.read().unwrap().clone(),
+            equaliserComparison: crate::EQUALISER_COMPARISON.read().unwrap().clone(),
+        }
+    }
+
     pub fn save(&self, app: &tauri::AppHandle) -> Result<(), Box<dyn std::error::Error>> {
         let configDir = app.path().app_local_data_dir()?;
         let configPath = configDir.join("config.json");
@@ -41,50 +80,1006 @@ impl AppConfig {
     }
 }
 
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FilterType {
+    Peak,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+    Notch,
+    BandPass,
+    AllPass,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FilterWidth {
+    Q(f32),
+    Slope(f32), // dB per octave, shelves only
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Filter {
+    pub filterType: FilterType,
+    pub frequency: f32, // Hz
+    pub gain: f32, // dB, ignored by passes, notches and all passes
+    pub width: FilterWidth,
+    pub enabled: bool,
+}
+impl Filter {
+    pub fn toConfig(&self) -> String {
+        let state = if self.enabled { "ON" } else { "OFF" };
+        let q = match self.width {
+            FilterWidth::Q(q) => q,
+            FilterWidth::Slope(_) => 0.707,
+        };
+
+        match (self.filterType, self.width) {
+            (FilterType::Peak, _) => format!("Filter: {} PK Fc {} Hz Gain {} dB Q {}", state, self.frequency, self.gain, q),
+            (FilterType::LowShelf, FilterWidth::Slope(slope)) => format!("Filter: {} LSC {} dB Fc {} Hz Gain {} dB", state, slope, self.frequency, self.gain),
+            (FilterType::LowShelf, FilterWidth::Q(q)) => format!("Filter: {} LSC Fc {} Hz Gain {} dB Q {}", state, self.frequency, self.gain, q),
+            (FilterType::HighShelf, FilterWidth::Slope(slope)) => format!("Filter: {} HSC {} dB Fc {} Hz Gain {} dB", state, slope, self.frequency, self.gain),
+            (FilterType::HighShelf, FilterWidth::Q(q)) => format!("Filter: {} HSC Fc {} Hz Gain {} dB Q {}", state, self.frequency, self.gain, q),
+            (FilterType::LowPass, _) => format!("Filter: {} LPQ Fc {} Hz Q {}", state, self.frequency, q),
+            (FilterType::HighPass, _) => format!("Filter: {} HPQ Fc {} Hz Q {}", state, self.frequency, q),
+            (FilterType::Notch, _) => format!("Filter: {} NO Fc {} Hz Q {}", state, self.frequency, q),
+            (FilterType::BandPass, _) => format!("Filter: {} BP Fc {} Hz Q {}", state, self.frequency, q),
+            (FilterType::AllPass, _) => format!("Filter: {} AP Fc {} Hz Q {}", state, self.frequency, q),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct GraphicEqPoint {
+    pub frequency: f32, // Hz
+    pub gain: f32, // dB
+}
+
+// What to do with GraphicEQ curves when importing a profile
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum GraphicEqImport {
+    Keep,
+    Fit,
+}
+
+// Curve a measurement is corrected towards, custom ones are read like measurements
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub enum TargetCurve {
+    Flat,
+    HarmanOverEar,
+    Custom(Vec<GraphicEqPoint>),
+}
+
+// Other equalisers' preset formats
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum EqualiserFormat {
+    EasyEffects, // output preset json
+    CamillaDsp, // yaml config
+}
+
+// Bounds the filter fitting stays within
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct FitLimits {
+    pub minFrequency: f32,
+    pub maxFrequency: f32,
+    pub maxGain: f32, // dB, both boost and cut
+    pub minQ: f32,
+    pub maxQ: f32,
+}
+impl Default for FitLimits {
+    fn default() -> Self {
+        Self {
+            minFrequency: 20.0,
+            maxFrequency: 16000.0,
+            maxGain: 12.0,
+            minQ: 0.4,
+            maxQ: 6.0,
+        }
+    }
+}
+
+// Speakers in the order they're listed in, which is also the order channels are kept in
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Speaker {
+    FrontLeft,
+    FrontRight,
+    Centre,
+    Lfe,
+    SideLeft,
+    SideRight,
+    BackLeft,
+    BackRight,
+}
+impl Speaker {
+    pub const ALL: [Self; 8] = [
+        Self::FrontLeft,
+        Self::FrontRight,
+        Self::Centre,
+        Self::Lfe,
+        Self::SideLeft,
+        Self::SideRight,
+        Self::BackLeft,
+        Self::BackRight,
+    ];
+
+    pub fn short(self) -> &'static str {
+        match self {
+            Self::FrontLeft => "FL",
+            Self::FrontRight => "FR",
+            Self::Centre => "C",
+            Self::Lfe => "LFE",
+            Self::SideLeft => "SL",
+            Self::SideRight => "SR",
+            Self::BackLeft => "BL",
+            Self::BackRight => "BR",
+        }
+    }
+
+    // Equalizer APO's channel names, it calls the back speakers rear ones
+    pub fn apoName(self) -> &'static str {
+        match self {
+            Self::FrontLeft => "L",
+            Self::FrontRight => "R",
+            Self::Centre => "C",
+            Self::Lfe => "SUB",
+            Self::SideLeft => "SL",
+            Self::SideRight => "SR",
+            Self::BackLeft => "RL",
+            Self::BackRight => "RR",
+        }
+    }
+
+    pub fn pipeWirePosition(self) -> &'static str {
+        match self {
+            Self::FrontLeft => "FL",
+            Self::FrontRight => "FR",
+            Self::Centre => "FC",
+            Self::Lfe => "LFE",
+            Self::SideLeft => "SL",
+            Self::SideRight => "SR",
+            Self::BackLeft => "RL",
+            Self::BackRight => "RR",
+        }
+    }
+
+    // The channel of an impulse response with `channels` channels this speaker uses, taking the file's channels to be
+    // in the order of the layout with that many, anything else gets the first one
+    pub fn irChannel(self, channels: usize) -> usize {
+        ChannelLayout::fromChannelCount(channels)
+            .and_then(|layout| layout.position(self))
+            .unwrap_or(0)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ChannelLayout {
+    Stereo,
+    Surround51,
+    Surround71,
+}
+impl ChannelLayout {
+    // the speakers in the order Windows, WAV files and CamillaDSP's devices number them
+    pub fn speakers(self) -> &'static [Speaker] {
+        use Speaker::*;
+
+        match self {
+            Self::Stereo => &[FrontLeft, FrontRight],
+            Self::Surround51 => &[FrontLeft, FrontRight, Centre, Lfe, SideLeft, SideRight],
+            Self::Surround71 => &[FrontLeft, FrontRight, Centre, Lfe, BackLeft, BackRight, SideLeft, SideRight],
+        }
+    }
+
+    pub fn position(self, speaker: Speaker) -> Option<usize> {
+        self.speakers().iter().position(|&s| s == speaker)
+    }
+
+    pub fn fromChannelCount(channels: usize) -> Option<Self> {
+        match channels {
+            2 => Some(Self::Stereo),
+            6 => Some(Self::Surround51),
+            8 => Some(Self::Surround71),
+            _ => None,
+        }
+    }
+
+    // the smallest layout with all of the speakers
+    pub fn covering(speakers: impl IntoIterator<Item = Speaker>) -> Self {
+        speakers.into_iter().fold(Self::Stereo, |layout, speaker| match speaker {
+            Speaker::BackLeft | Speaker::BackRight => Self::Surround71,
+            Speaker::Centre | Speaker::Lfe | Speaker::SideLeft | Speaker::SideRight if layout == Self::Stereo => Self::Surround51,
+            _ => layout,
+        })
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "EqualiserChannelSettingsRepr")]
 pub struct EqualiserChannelSettings {
+    pub speaker: Speaker,
     pub preamp: f32,
-    pub channelLeft: bool,
-    pub bassGain: f32,
-    pub lowGain: f32,
-    pub midGain: f32,
-    pub highGain: f32,
-    pub trebleGain: f32,
+    pub filters: Vec<Filter>,
+    pub graphicEq: Vec<GraphicEqPoint>, // empty when unused, interpolated on a log frequency scale like APO does
+    pub convolution: Option<String>, // absolute path of a WAV impulse response, applied after the filters
+    pub autoHeadroom: bool, // preamp follows the filters' maximum boost
+    pub headroomMargin: f32, // dB of extra headroom below the maximum boost
+    pub trim: f32, // dB, level matching the speaker, kept per channel even while linked
+    pub delay: f32, // ms, time aligning the speaker, kept per channel even while linked
 }
+impl EqualiserChannelSettings {
+    pub fn toConfig(&self) -> String {
+        let mut config = format!("Preamp: {} dB\n", self.preamp);
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
-pub struct EqualiserSettings(pub EqualiserChannelSettings, pub EqualiserChannelSettings); // 0 is left, 1 is right
+        for filter in &self.filters {
+            config.push_str(&filter.toConfig());
+            config.push('\n');
+        }
+        if !self.graphicEq.is_empty() {
+            let points: Vec<String> = self.graphicEq.iter().map(|p| format!("{} {}", p.frequency, p.gain)).collect();
+            config.push_str(&format!("GraphicEQ: {}\n", points.join("; ")));
+        }
+        if let Some(path) = &self.convolution {
+            config.push_str(&format!("Convolution: {}\n", path));
+        }
+
+        config
+    }
+
+    // APO adds up preamps, so the trim is simply a second one
+    pub fn trimConfig(&self) -> String {
+        let mut config = String::new();
+
+        if self.trim != 0. {
+            config.push_str(&format!("Preamp: {} dB\n", self.trim));
+        }
+        if self.delay > 0. {
+            config.push_str(&format!("Delay: {} ms\n", self.delay));
+        }
+
+        config
+    }
+
+    pub fn hasTrims(&self) -> bool {
+        self.trim != 0. || self.delay > 0.
+    }
+
+    pub fn sameAs(&self, other: &Self) -> bool {
+        self.preamp == other.preamp && self.filters == other.filters && self.graphicEq == other.graphicEq && self.convolution == other.convolution
+    }
+
+    pub fn new(speaker: Speaker) -> Self {
+        let filter = |filterType, frequency, q| Filter {
+            filterType,
+            frequency,
+            gain: 0.0,
+            width: FilterWidth::Q(q),
+            enabled: true,
+        };
+
+        Self {
+            speaker,
+            preamp: 0.0,
+            graphicEq: vec![],
+            convolution: None,
+            autoHeadroom: false,
+            headroomMargin: 0.5,
+            trim: 0.0,
+            delay: 0.0,
+            filters: vec![
+                filter(FilterType::LowShelf, 105.0, 0.7),
+                filter(FilterType::Peak, 250.0, 1.0),
+                filter(FilterType::Peak, 1000.0, 1.0),
+                filter(FilterType::Peak, 4000.0, 1.0),
+                filter(FilterType::HighShelf, 10000.0, 0.7),
+            ],
+        }
+    }
+
+    // the settings `self` shares while linked, on this channel's speaker with its own trims
+    pub fn linkedTo(&self, other: &Self) -> Self {
+        Self {
+            speaker: self.speaker,
+            trim: self.trim,
+            delay: self.delay,
+            ..other.clone()
+        }
+    }
+}
+
+// configs written before the filter list existed only stored five fixed gains,
+// and stereo ones told their channels apart with channelLeft instead of a speaker
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum EqualiserChannelSettingsRepr {
+    Current {
+        #[serde(default)]
+        speaker: Option<Speaker>,
+        #[serde(default)]
+        channelLeft: Option<bool>,
+        preamp: f32,
+        filters: Vec<Filter>,
+        #[serde(default)]
+        graphicEq: Vec<GraphicEqPoint>,
+        #[serde(default)]
+        convolution: Option<String>,
+        #[serde(default)]
+        autoHeadroom: bool,
+        #[serde(default = "defaultHeadroomMargin")]
+        headroomMargin: f32,
+        #[serde(default)]
+        trim: f32,
+        #[serde(default)]
+        delay: f32,
+    },
+    Legacy {
+        preamp: f32,
+        channelLeft: bool,
+        bassGain: f32,
+        lowGain: f32,
+        midGain: f32,
+        highGain: f32,
+        trebleGain: f32,
+    },
+}
+impl From<EqualiserChannelSettingsRepr> for EqualiserChannelSettings {
+    fn from(repr: EqualiserChannelSettingsRepr) -> Self {
+        let stereoSpeaker = |channelLeft| if channelLeft { Speaker::FrontLeft } else { Speaker::FrontRight };
+
+        match repr {
+            EqualiserChannelSettingsRepr::Current { speaker, channelLeft, preamp, filters, graphicEq, convolution, autoHeadroom, headroomMargin, trim, delay } => Self {
+                speaker: speaker.unwrap_or_else(|| stereoSpeaker(channelLeft.unwrap_or(true))),
+                preamp,
+                filters,
+                graphicEq,
+                convolution,
+                autoHeadroom,
+                headroomMargin,
+                trim,
+                delay,
+            },
+            EqualiserChannelSettingsRepr::Legacy { preamp, channelLeft, bassGain, lowGain, midGain, highGain, trebleGain } => {
+                let filter = |filterType, frequency, gain, q| Filter {
+                    filterType,
+                    frequency,
+                    gain,
+                    width: FilterWidth::Q(q),
+                    enabled: true,
+                };
+
+                Self {
+                    speaker: stereoSpeaker(channelLeft),
+                    preamp,
+                    graphicEq: vec![],
+                    convolution: None,
+                    autoHeadroom: false,
+                    headroomMargin: defaultHeadroomMargin(),
+                    trim: 0.0,
+                    delay: 0.0,
+                    filters: vec![
+                        filter(FilterType::LowShelf, 250.0, bassGain, 0.707),
+                        filter(FilterType::Peak, 500.0, lowGain, 2.0),
+                        filter(FilterType::Peak, 750.0, midGain, 3.0),
+                        filter(FilterType::Peak, 1000.0, highGain, 4.0),
+                        filter(FilterType::HighShelf, 1250.0, trebleGain, 0.707),
+                    ],
+                }
+            }
+        }
+    }
+}
+
+// Routing applied before the filters, so the channels' EQ still corrects each driver
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct SpatialSettings {
+    pub crossfeed: bool,
+    pub crossfeedLevel: f32, // dB of the opposite channel mixed in below the direct one
+    pub crossfeedCutoff: f32, // Hz, the crossfed signal is low passed like the head shadows it
+    pub balance: f32, // -1 is fully left, 1 fully right
+    pub mono: bool,
+    pub swap: bool,
+    pub width: f32, // 0 is mono, 1 unchanged, 2 the side signal doubled
+}
+impl Default for SpatialSettings {
+    fn default() -> Self {
+        Self {
+            crossfeed: false,
+            crossfeedLevel: -4.5,
+            crossfeedCutoff: 700.0,
+            balance: 0.0,
+            mono: false,
+            swap: false,
+            width: 1.0,
+        }
+    }
+}
+impl SpatialSettings {
+    // about the time sound takes around the head
+    pub const CROSSFEED_DELAY_MS: f32 = 0.3;
+
+    // Gains from the inputs (columns) to the outputs (rows), left first
+    pub fn routingMatrix(&self) -> [[f32; 2]; 2] {
+        // mid/side: the side signal scales with the width, mono is no side at all
+        let width = if self.mono { 0. } else { self.width.clamp(0., 2.) };
+        let (direct, opposite) = ((1. + width) / 2., (1. - width) / 2.);
+        let mut matrix = [[direct, opposite], [opposite, direct]];
+
+        if self.swap {
+            matrix.swap(0, 1);
+        }
+
+        let balance = self.balance.clamp(-1., 1.);
+        for (row, gain) in matrix.iter_mut().zip([1. - balance.max(0.), 1. + balance.min(0.)]) {
+            row.iter_mut().for_each(|g| *g *= gain);
+        }
+
+        matrix
+    }
+
+    // The settings a routing matrix comes from, None when no width, balance and swap give it.
+    // Mono and a width of 0 route alike, it comes back as mono.
+    pub fn fromRoutingMatrix(matrix: [[f32; 2]; 2]) -> Option<Self> {
+        // direct and opposite gains add up to 1, what a row sums to is its balance gain
+        let gains = [matrix[0][0] + matrix[0][1], matrix[1][0] + matrix[1][1]];
+        let row = if gains[0] >= gains[1] { 0 } else { 1 };
+        if gains[row] <= 0. {
+            return None;
+        }
+
+        // the direct gain is the larger one, it's on the other side when swapped
+        let (own, other) = (matrix[row][row] / gains[row], matrix[row][1 - row] / gains[row]);
+        // rounded off so float error doesn't leave a sliver of balance or width
+        let round = |value: f32| (value * 10_000.).round() / 10_000.;
+        let width = round((own - other).abs());
+        let balance = round(if gains[0] < 1. { 1. - gains[0] } else { gains[1] - 1. });
+
+        let settings = Self { balance, width: if width == 0. { 1. } else { width }, mono: width == 0., swap: own < other, ..Self::default() };
+        let routed = settings.routingMatrix();
+        let matches = routed.iter().flatten().zip(matrix.iter().flatten()).all(|(a, b)| (a - b).abs() < 1e-4);
+
+        matches.then_some(settings)
+    }
+
+    // Direct and crossfed gains, normalised so a centred sound keeps its level
+    pub fn crossfeedGains(&self) -> (f32, f32) {
+        let cross = 10f32.powf(self.crossfeedLevel.min(0.) / 20.);
+        (1. / (1. + cross), cross / (1. + cross))
+    }
+
+    pub fn isNeutral(&self) -> bool {
+        !self.crossfeed && self.routingMatrix() == [[1., 0.], [0., 1.]]
+    }
+
+    // dB the routing can raise a full scale signal by, widening adds the side signal on top of the mid one
+    pub fn maxBoost(&self) -> f64 {
+        let peak = self.routingMatrix().iter().map(|row| row[0].abs() + row[1].abs()).fold(0f32, f32::max);
+        20. * (peak.max(1e-6) as f64).log10()
+    }
+
+    pub fn toConfig(&self) -> String {
+        if self.isNeutral() {
+            return String::new();
+        }
+
+        let mut config = String::new();
+
+        let matrix = self.routingMatrix();
+        if matrix != [[1., 0.], [0., 1.]] {
+            config.push_str(&format!("Copy: {} {}\n", copyTerms("L", matrix[0]), copyTerms("R", matrix[1])));
+        }
+
+        if self.crossfeed {
+            let (direct, cross) = self.crossfeedGains();
+            // virtual channels hold the signal crossing over to the other ear
+            config.push_str("Copy: XL=R XR=L\n");
+            config.push_str("Channel: XL XR\n");
+            config.push_str(&format!("Filter: ON LP Fc {} Hz\n", self.crossfeedCutoff));
+            config.push_str(&format!("Delay: {} ms\n", Self::CROSSFEED_DELAY_MS));
+            config.push_str(&format!("Copy: L={0}*L+{1}*XL R={0}*R+{1}*XR\n", direct, cross));
+        }
+
+        config
+    }
+}
+
+// "L=0.75*L+0.25*R" or "L=1.25*L-0.25*R", leaving out silent inputs
+fn copyTerms(output: &str, gains: [f32; 2]) -> String {
+    let mut expression = String::new();
+    for (input, gain) in ["L", "R"].iter().zip(gains).filter(|(_, gain)| *gain != 0.) {
+        let sign = match (gain < 0., expression.is_empty()) {
+            (true, _) => "-",
+            (false, true) => "",
+            (false, false) => "+",
+        };
+        expression.push_str(&format!("{}{}*{}", sign, gain.abs(), input));
+    }
+
+    if expression.is_empty() {
+        format!("{}=0", output)
+    } else {
+        format!("{}={}", output, expression)
+    }
+}
+
+// Equal loudness compensation following the preamp, which is taken as the volume
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct LoudnessSettings {
+    pub enabled: bool,
+    pub referenceLevel: f32, // dB SPL playback reaches with a 0 dB preamp, where no compensation is needed
+}
+impl Default for LoudnessSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            referenceLevel: 83.0,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Compressor {
+    pub enabled: bool,
+    pub threshold: f32, // dBFS
+    pub ratio: f32, // 1:n above the threshold
+    pub attack: f32, // ms
+    pub release: f32, // ms
+    pub knee: f32, // dB either side of the threshold the ratio eases in over
+    pub makeupGain: f32, // dB
+}
+impl Default for Compressor {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: -20.0,
+            ratio: 4.0,
+            attack: 10.0,
+            release: 200.0,
+            knee: 6.0,
+            makeupGain: 0.0,
+        }
+    }
+}
+
+// A brickwall compressor, nothing gets past the threshold
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Limiter {
+    pub enabled: bool,
+    pub threshold: f32, // dBFS
+    pub release: f32, // ms
+}
+impl Default for Limiter {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: -1.0,
+            release: 50.0,
+        }
+    }
+}
+
+// Level dependent stages after the filters, the compressor then the limiter, shared by every channel
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(default)]
+pub struct DynamicsSettings {
+    pub compressor: Compressor,
+    pub limiter: Limiter,
+}
+impl DynamicsSettings {
+    // the stages in use that `support` can't run, named for the UI
+    pub fn unsupported(&self, support: &DynamicsSupport) -> Vec<String> {
+        let mut unsupported = vec![];
+
+        if self.compressor.enabled && !support.compressor {
+            unsupported.push("Compressor".into());
+        }
+        if self.limiter.enabled && !support.limiter {
+            unsupported.push("Limiter".into());
+        }
+
+        unsupported
+    }
+
+    // the stages `support` can run, the others disabled
+    pub fn limitedTo(&self, support: &DynamicsSupport) -> Self {
+        Self {
+            compressor: Compressor { enabled: self.compressor.enabled && support.compressor, ..self.compressor },
+            limiter: Limiter { enabled: self.limiter.enabled && support.limiter, ..self.limiter },
+        }
+    }
+}
+
+// Which dynamics stages a backend can run
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct DynamicsSupport {
+    pub compressor: bool,
+    pub limiter: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "EqualiserSettingsRepr")]
+pub struct EqualiserSettings {
+    pub layout: ChannelLayout,
+    pub channels: Vec<EqualiserChannelSettings>, // one per speaker of the layout, in its order
+    pub linked: bool, // the first channel drives all of them
+    pub spatial: SpatialSettings, // only routes the front pair
+    pub loudness: LoudnessSettings,
+    pub dynamics: DynamicsSettings,
+}
 impl Default for EqualiserSettings {
     fn default() -> Self {
-        Self(
-            EqualiserChannelSettings {
-                preamp: 0.0,
-                channelLeft: true,
-                bassGain: 0.0,
-                lowGain: 0.0,
-                midGain: 0.0,
-                highGain: 0.0,
-                trebleGain: 0.0,
-            }, 
-            EqualiserChannelSettings {
-                preamp: 0.0,
-                channelLeft: false,
-                bassGain: 0.0,
-                lowGain: 0.0,
-                midGain: 0.0,
-                highGain: 0.0,
-                trebleGain: 0.0,
-            }
-        )
+        Self::fromChannels(ChannelLayout::Stereo, vec![])
     }
 }
 impl EqualiserSettings {
-    pub fn toConfig(&self) -> String {
-        format!(
-            "Preamp: {} dB\n\nFilter: ON LS Fc 250 Hz Gain {}dB\nFilter: ON PK Fc 500 Hz Gain {}dB Q 2.0\nFilter: ON PK Fc 750 Hz Gain {}dB Q 3.0\nFilter: ON PK Fc 1000 Hz Gain {}dB Q 4.0\nFilter: ON HS Fc 1250 Hz Gain {}dB",
-            self.0.preamp, self.0.bassGain, self.0.lowGain, self.0.midGain, self.0.highGain, self.0.trebleGain
-        )
+    // Settings on `layout` with whatever channels are given, missing speakers get default ones.
+    // Linked when every channel ended up the same, routing and loudness compensation start out neutral.
+    pub fn fromChannels(layout: ChannelLayout, channels: Vec<EqualiserChannelSettings>) -> Self {
+        let settings = Self {
+            layout,
+            channels,
+            linked: false,
+            spatial: SpatialSettings::default(),
+            loudness: LoudnessSettings::default(),
+            dynamics: DynamicsSettings::default(),
+        }
+        .withLayout(layout);
+
+        Self {
+            linked: settings.allSame(),
+            ..settings
+        }
     }
+
+    pub fn channel(&self, speaker: Speaker) -> Option<&EqualiserChannelSettings> {
+        self.channels.iter().find(|c| c.speaker == speaker)
+    }
+
+    fn allSame(&self) -> bool {
+        self.channels.windows(2).all(|pair| pair[0].sameAs(&pair[1]))
+    }
+
+    // whether a single section serves every channel
+    pub fn isLinked(&self) -> bool {
+        self.linked || self.allSame()
+    }
+
+    // the stored link, or when there's none whether the channels are alike, linking others would overwrite them
+    fn linkedIf(self, linked: Option<bool>) -> Self {
+        let linked = linked.unwrap_or_else(|| self.allSame());
+        Self { linked, ..self }
+    }
+
+    // the config APO runs at `sampleRate`, which the loudness compensation is fitted at
+    pub fn toConfig(&self, sampleRate: f64) -> String {
+        let mut config = self.spatial.toConfig();
+        let settings = self.compensated(sampleRate);
+
+        if self.isLinked() {
+            // "all" would take in the crossfeed's virtual channels too
+            let all = match self.spatial.crossfeed {
+                true => self.channels.iter().map(|c| c.speaker.apoName()).collect::<Vec<_>>().join(" "),
+                false => "all".into(),
+            };
+            config.push_str(&format!("Channel: {}\n{}", all, settings.channels[0].toConfig()));
+            for channel in settings.channels.iter().filter(|c| c.hasTrims()) {
+                config.push_str(&format!("\nChannel: {}\n{}", channel.speaker.apoName(), channel.trimConfig()));
+            }
+        } else {
+            let sections: Vec<String> = settings
+                .channels
+                .iter()
+                .map(|c| format!("Channel: {}\n{}{}", c.speaker.apoName(), c.toConfig(), c.trimConfig()))
+                .collect();
+            config.push_str(&sections.join("\n"));
+        }
+
+        config
+    }
+
+    // the channels with their loudness compensation for `sampleRate` added to the filters, what the backends actually run
+    pub fn compensated(&self, sampleRate: f64) -> Self {
+        let mut settings = self.clone();
+        for channel in &mut settings.channels {
+            channel.filters.extend(loudnessFilters(&self.loudness, channel.preamp, sampleRate));
+        }
+
+        settings
+    }
+
+    // replaces the preamp of every channel with auto headroom enabled by its clipping-safe value at `sampleRate`,
+    // peaks close to Nyquist are warped differently at every rate
+    pub fn withAutoHeadroom(mut self, sampleRate: f64) -> Self {
+        let routingBoost = self.spatial.maxBoost().max(0.);
+
+        for channel in &mut self.channels {
+            if channel.autoHeadroom {
+                // a boosting trim needs the headroom as much as a boosting filter does
+                let boost = routingBoost + channel.trim.max(0.) as f64;
+                channel.preamp = -((maxBoost(channel, sampleRate) + boost) as f32) - channel.headroomMargin.max(0.);
+
+                // the lower preamp asks for more compensation, which asks for more headroom. The compensation grows
+                // slower than the level drops, so each round moves the preamp less until it settles.
+                for _ in 0..MAX_HEADROOM_ROUNDS {
+                    let compensated = EqualiserChannelSettings {
+                        filters: [channel.filters.clone(), loudnessFilters(&self.loudness, channel.preamp, sampleRate)].concat(),
+                        ..channel.clone()
+                    };
+                    let preamp = -((maxBoost(&compensated, sampleRate) + boost) as f32) - channel.headroomMargin.max(0.);
+                    let settled = (preamp - channel.preamp).abs() < HEADROOM_TOLERANCE;
+
+                    channel.preamp = preamp;
+                    if settled {
+                        break;
+                    }
+                }
+            }
+        }
+
+        self
+    }
+
+    // Keeps one channel per speaker of `layout` in its order, new speakers start out like the first channel while
+    // linked and flat otherwise
+    pub fn withLayout(mut self, layout: ChannelLayout) -> Self {
+        let first = self.channels.first().cloned();
+        let mut channels = std::mem::take(&mut self.channels);
+
+        self.channels = layout
+            .speakers()
+            .iter()
+            .map(|&speaker| match channels.iter().position(|c| c.speaker == speaker) {
+                Some(i) => channels.swap_remove(i),
+                None => match (&first, self.linked) {
+                    (Some(first), true) => EqualiserChannelSettings::new(speaker).linkedTo(first),
+                    _ => EqualiserChannelSettings::new(speaker),
+                },
+            })
+            .collect();
+        self.layout = layout;
+
+        self
+    }
+
+    // fits the channels to the layout and copies the first onto the others while the channels are linked
+    pub fn synchronised(self) -> Self {
+        let layout = self.layout;
+        let mut settings = self.withLayout(layout);
+
+        if settings.linked {
+            let first = settings.channels[0].clone();
+            for channel in settings.channels.iter_mut().skip(1) {
+                *channel = channel.linkedTo(&first);
+            }
+        }
+
+        settings
+    }
+
+    // Linked settings are one curve for every speaker, so they're moved onto `current`'s layout and keep its trims.
+    // Split ones were made for their own speakers and are left alone.
+    pub fn onSpeakersOf(self, current: &Self) -> Self {
+        if !self.linked {
+            return self;
+        }
+
+        let mut settings = self.withLayout(current.layout);
+        for channel in &mut settings.channels {
+            if let Some(existing) = current.channel(channel.speaker) {
+                channel.trim = existing.trim;
+                channel.delay = existing.delay;
+            }
+        }
+
+        settings
+    }
+}
+
+// configs written before the channel map stored a left and a right channel as a tuple
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum EqualiserSettingsRepr {
+    Current {
+        layout: ChannelLayout,
+        channels: Vec<EqualiserChannelSettings>,
+        // configs from before linking existed are linked only when that doesn't change their channels
+        #[serde(default)]
+        linked: Option<bool>,
+        #[serde(default)]
+        spatial: SpatialSettings,
+        #[serde(default)]
+        loudness: LoudnessSettings,
+        #[serde(default)]
+        dynamics: DynamicsSettings,
+    },
+    Stereo(StereoEqualiserSettings),
+}
+
+#[derive(serde::Deserialize)]
+struct StereoEqualiserSettings(
+    EqualiserChannelSettings,
+    EqualiserChannelSettings,
+    #[serde(default)] Option<bool>,
+    #[serde(default)] SpatialSettings,
+    #[serde(default)] LoudnessSettings,
+);
+
+impl From<EqualiserSettingsRepr> for EqualiserSettings {
+    fn from(repr: EqualiserSettingsRepr) -> Self {
+        match repr {
+            // hand edited or truncated configs get a channel per speaker, everything downstream indexes them
+            EqualiserSettingsRepr::Current { layout, channels, linked, spatial, loudness, dynamics } => Self {
+                layout,
+                channels,
+                linked: false,
+                spatial,
+                loudness,
+                dynamics,
+            }
+            .linkedIf(linked)
+            .withLayout(layout),
+            EqualiserSettingsRepr::Stereo(StereoEqualiserSettings(left, right, linked, spatial, loudness)) => Self {
+                layout: ChannelLayout::Stereo,
+                channels: vec![
+                    EqualiserChannelSettings { speaker: Speaker::FrontLeft, ..left },
+                    EqualiserChannelSettings { speaker: Speaker::FrontRight, ..right },
+                ],
+                linked: false,
+                spatial,
+                loudness,
+                dynamics: DynamicsSettings::default(),
+            }
+            .linkedIf(linked),
+        }
+    }
+}
+
+fn defaultHeadroomMargin() -> f32 {
+    0.5
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct EqualiserPreset {
+    pub name: String,
+    pub settings: EqualiserSettings,
+    #[serde(default)]
+    pub builtIn: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ApoImport {
+    pub settings: EqualiserSettings,
+    pub warnings: Vec<String>,
+}
+
+// a measurement corrected towards a target, with the name it was stored under
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct MeasurementMatch {
+    pub preset: EqualiserPreset,
+    pub warnings: Vec<String>,
+}
+
+// an exported file, with what the format couldn't hold
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct EqualiserExport {
+    pub contents: String,
+    pub warnings: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct EqualiserBackendSettings {
+    pub configDirOverride: Option<String>,
+    #[serde(default)]
+    pub deviceBindings: BTreeMap<String, String>, // output device id to the preset applied whenever it becomes the default
+}
+
+// Presets applied over the user's own settings, not saved since they're worked out again while running
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EqualiserOverrides {
+    pub device: Option<String>, // bound to the default output device
+    pub profile: Option<String>, // the application profile's
+}
+impl EqualiserOverrides {
+    // the profile's is more specific, so it wins over the device's
+    pub fn preset(&self) -> Option<&String> {
+        self.profile.as_ref().or(self.device.as_ref())
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ImpulseResponseInfo {
+    pub path: String,
+    pub sampleRate: u32,
+    pub channels: u16,
+    pub length: u32, // samples per channel
+    pub duration: f32, // seconds
+    pub warnings: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ComparisonSlot {
+    A,
+    B,
+}
+impl ComparisonSlot {
+    pub fn other(self) -> Self {
+        match self {
+            Self::A => Self::B,
+            Self::B => Self::A,
+        }
+    }
+}
+
+// A/B comparison and bypass, the playing slot's settings are the equaliser settings themselves
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EqualiserComparison {
+    pub bypassed: bool,
+    pub slot: ComparisonSlot,
+    pub stored: Option<EqualiserSettings>, // the slot not playing, None until the first switch copies the playing one
+}
+impl Default for EqualiserComparison {
+    fn default() -> Self {
+        Self {
+            bypassed: false,
+            slot: ComparisonSlot::A,
+            stored: None,
+        }
+    }
+}
+impl EqualiserComparison {
+    // the slot being heard, None while bypassed
+    pub fn playing(&self) -> Option<ComparisonSlot> {
+        (!self.bypassed).then_some(self.slot)
+    }
+
+    pub fn status(&self) -> ComparisonStatus {
+        ComparisonStatus {
+            bypassed: self.bypassed,
+            slot: self.slot,
+        }
+    }
+}
+
+// What the UI and the tray show of the comparison
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ComparisonStatus {
+    pub bypassed: bool,
+    pub slot: ComparisonSlot,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct EqualiserStatus {
+    pub backend: String,
+    pub installed: bool,
+    pub version: Option<String>,
+    pub configDir: String,
+    pub writable: bool,
+    pub devices: Vec<String>, // devices the backend is installed on
+    pub dynamics: DynamicsSupport,
+    pub seamlessSwitching: bool, // false when bypassing, or switching to a slot needing a different graph, interrupts the audio
+}
+
+// The PipeWire client running the filter-chain, restarts are debounced so dragging a slider doesn't respawn it each step
+pub struct PipeWireFilter {
+    pub process: Option<std::process::Child>,
+    pub topology: Option<String>, // of the config last written, running or about to be
+    pub restarts: u64, // bumped by every restart asked for, only the latest one goes ahead
+    pub restarting: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+pub struct ResponsePoint {
+    pub frequency: f32, // Hz
+    pub magnitude: f32, // dB
+    pub phase: f32, // degrees
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct EqualiserResponse {
+    pub channels: BTreeMap<Speaker, Vec<ResponsePoint>>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -100,12 +1095,42 @@ pub struct Vector2 {
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct AppProfile {
+    pub executable: String, // file name, case insensitive and the .exe is optional
+    pub preset: Option<String>, // None leaves the equaliser alone
+    pub visualiserSettings: Option<VisualiserSettings>, // None keeps the user's own look
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct AppProfileSettings {
+    pub enabled: bool,
+    pub rules: Vec<AppProfile>, // first match wins
+    pub fallback: Option<AppProfile>, // applied when no rule matches, its executable is ignored
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct VisualiserSettings {
     pub barsColour: (u8, u8, u8, u8), // rgba, 0-255
     pub visualiserType: VisualiserType,
     pub useDesktopBackground: bool,
     pub resolution: u16,
     pub screen: Option<String>,
+    pub targetFps: u16, // 0 leaves it unlimited
+    pub throttledFps: u16, // used instead of targetFps while occluded or on battery
+    pub adaptiveFrameRate: bool,
+    pub silenceThreshold: f32, // dBFS
+    pub silenceTimeout: f32, // seconds below the threshold before frames stop, 0 disables
+    pub minFrequency: f32, // Hz
+    pub maxFrequency: f32, // Hz
+    pub bandEdges: Vec<f32>, // Hz, only used by VisualiserType::Custom
+    pub bandAggregation: BandAggregation,
+    pub spatialSmoothing: SpatialSmoothing,
+    pub smoothingRadius: u16, // bands either side, Gaussian and SavitzkyGolay
+    pub smoothingSigma: f32, // bands, Gaussian
+    pub monstercatFalloff: f32, // divides a bar's pull on each band further out, Monstercat
+    pub latencyCompensation: BTreeMap<String, u16>, // ms per output device id, 0-500
 }
 impl Default for VisualiserSettings {
     fn default() -> Self {
@@ -115,13 +1140,380 @@ impl Default for VisualiserSettings {
             useDesktopBackground: true,
             resolution: 128,
             screen: None,
+            targetFps: 60,
+            throttledFps: 15,
+            adaptiveFrameRate: true,
+            silenceThreshold: -70.0,
+            silenceTimeout: 5.0,
+            // the min frequency is experimented with a bit and (partially) prevents the left side of the visualiser from simply being completely flat
+            minFrequency: 75.0,
+            maxFrequency: 20_000.0,
+            bandEdges: vec![],
+            bandAggregation: BandAggregation::Mean,
+            spatialSmoothing: SpatialSmoothing::None,
+            smoothingRadius: 3,
+            smoothingSigma: 1.5,
+            monstercatFalloff: 1.5,
+            latencyCompensation: BTreeMap::new(),
         }
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum VisualiserType {
     Linear1, // linear by frequency
     Linear2, // linear by amount of separate frequencies
     Log, // normal logarithmic
+    Custom, // explicit band edges
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum BandAggregation {
+    Mean,
+    Max,
+    Rms,
+    Energy, // sum of squared volumes
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SpatialSmoothing {
+    None,
+    Gaussian,
+    SavitzkyGolay,
+    Monstercat,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn filter(filterType: FilterType, frequency: f32, gain: f32, width: FilterWidth) -> Filter {
+        Filter { filterType, frequency, gain, width, enabled: true }
+    }
+
+    fn curve(speaker: Speaker) -> EqualiserChannelSettings {
+        EqualiserChannelSettings {
+            preamp: -6.5,
+            filters: vec![
+                filter(FilterType::LowShelf, 105., 4.5, FilterWidth::Q(0.71)),
+                filter(FilterType::LowShelf, 80., 2., FilterWidth::Slope(12.)),
+                filter(FilterType::Peak, 1234.5, -3.25, FilterWidth::Q(1.41)),
+                Filter { enabled: false, ..filter(FilterType::Peak, 3000., 6., FilterWidth::Q(4.)) },
+                filter(FilterType::HighShelf, 10_000., -2., FilterWidth::Slope(6.)),
+                filter(FilterType::HighPass, 25., 0., FilterWidth::Q(0.5)),
+                filter(FilterType::LowPass, 18_000., 0., FilterWidth::Q(0.707)),
+                filter(FilterType::Notch, 60., 0., FilterWidth::Q(30.)),
+                filter(FilterType::BandPass, 500., 0., FilterWidth::Q(2.)),
+                filter(FilterType::AllPass, 2000., 0., FilterWidth::Q(0.9)),
+            ],
+            ..EqualiserChannelSettings::new(speaker)
+        }
+    }
+
+    fn roundTrip(settings: &EqualiserSettings) -> ApoImport {
+        let config = settings.toConfig(48_000.);
+        let import = crate::apo::parseConfig(&config, Path::new("."));
+        assert!(import.warnings.is_empty(), "{:?} from\n{}", import.warnings, config);
+
+        import
+    }
+
+    #[test]
+    fn filtersUseApoSyntax() {
+        assert_eq!(curve(Speaker::FrontLeft).toConfig(), [
+            "Preamp: -6.5 dB",
+            "Filter: ON LSC Fc 105 Hz Gain 4.5 dB Q 0.71",
+            "Filter: ON LSC 12 dB Fc 80 Hz Gain 2 dB",
+            "Filter: ON PK Fc 1234.5 Hz Gain -3.25 dB Q 1.41",
+            "Filter: OFF PK Fc 3000 Hz Gain 6 dB Q 4",
+            "Filter: ON HSC 6 dB Fc 10000 Hz Gain -2 dB",
+            "Filter: ON HPQ Fc 25 Hz Q 0.5",
+            "Filter: ON LPQ Fc 18000 Hz Q 0.707",
+            "Filter: ON NO Fc 60 Hz Q 30",
+            "Filter: ON BP Fc 500 Hz Q 2",
+            "Filter: ON AP Fc 2000 Hz Q 0.9",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn linkedStereoRoundTrips() {
+        let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![curve(Speaker::FrontLeft), curve(Speaker::FrontRight)]);
+        assert!(settings.linked);
+        assert!(settings.toConfig(48_000.).starts_with("Channel: all\n"));
+
+        assert_eq!(roundTrip(&settings).settings, settings);
+    }
+
+    #[test]
+    fn splitChannelsRoundTrip() {
+        let mut right = curve(Speaker::FrontRight);
+        right.preamp = -3.;
+        right.filters.truncate(3);
+        let stereo = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![curve(Speaker::FrontLeft), right]);
+        assert!(!stereo.linked);
+        assert!(stereo.toConfig(48_000.).starts_with("Channel: L\n"));
+        assert_eq!(roundTrip(&stereo).settings, stereo);
+
+        let channels = ChannelLayout::Surround71
+            .speakers()
+            .iter()
+            .enumerate()
+            .map(|(i, &speaker)| EqualiserChannelSettings { preamp: -(i as f32), ..curve(speaker) })
+            .collect();
+        let surround = EqualiserSettings::fromChannels(ChannelLayout::Surround71, channels);
+        assert_eq!(roundTrip(&surround).settings, surround);
+    }
+
+    #[test]
+    fn convolutionRoundTrips() {
+        let path = std::env::temp_dir().join("slyshmefx-room.wav").to_string_lossy().into_owned();
+        let channel = |speaker| EqualiserChannelSettings { convolution: Some(path.clone()), ..curve(speaker) };
+        let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![channel(Speaker::FrontLeft), channel(Speaker::FrontRight)]);
+
+        assert_eq!(roundTrip(&settings).settings, settings);
+    }
+
+    // APO has no separate trim, it comes back added to the preamp
+    #[test]
+    fn trimsRoundTripIntoThePreamp() {
+        let mut settings = EqualiserSettings::fromChannels(ChannelLayout::Surround51, vec![curve(Speaker::FrontLeft)]);
+        settings.linked = true;
+        let mut settings = settings.synchronised();
+        for (i, channel) in settings.channels.iter_mut().enumerate() {
+            channel.trim = -(i as f32);
+            channel.delay = i as f32 * 0.5;
+        }
+
+        let parsed = roundTrip(&settings).settings;
+        assert_eq!(parsed.layout, ChannelLayout::Surround51);
+        for (original, parsed) in settings.channels.iter().zip(&parsed.channels) {
+            assert_eq!(parsed.speaker, original.speaker);
+            assert_eq!(parsed.preamp, original.preamp + original.trim);
+            assert_eq!(parsed.delay, original.delay);
+            assert_eq!(parsed.filters, original.filters);
+        }
+    }
+
+    // a high shelf reaches its full boost by 20 kHz at 44.1 kHz but is still rising at 96 kHz, the headroom has to follow
+    #[test]
+    fn headroomFollowsTheSampleRate() {
+        let channel = EqualiserChannelSettings {
+            filters: vec![filter(FilterType::HighShelf, 16_000., 9., FilterWidth::Q(0.707))],
+            autoHeadroom: true,
+            headroomMargin: 0.,
+            ..EqualiserChannelSettings::new(Speaker::FrontLeft)
+        };
+        let settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![channel.clone(), channel.linkedTo(&EqualiserChannelSettings::new(Speaker::FrontRight))]);
+
+        for sampleRate in [44_100., 48_000., 96_000.] {
+            let preamp = settings.clone().withAutoHeadroom(sampleRate).channels[0].preamp as f64;
+            assert!((preamp + maxBoost(&channel, sampleRate)).abs() < 1e-4, "{} Hz: preamp {}", sampleRate, preamp);
+        }
+        let (low, high) = (settings.clone().withAutoHeadroom(44_100.), settings.withAutoHeadroom(96_000.));
+        assert!((low.channels[0].preamp - high.channels[0].preamp).abs() > 0.1);
+    }
+
+    #[test]
+    fn profilePresetsOverrideDevicePresets() {
+        let mut overrides = EqualiserOverrides::default();
+        assert_eq!(overrides.preset(), None);
+
+        overrides.device = Some("headphones".into());
+        assert_eq!(overrides.preset().map(String::as_str), Some("headphones"));
+        overrides.profile = Some("game".into());
+        assert_eq!(overrides.preset().map(String::as_str), Some("game"));
+
+        // leaving the application falls back to the device's, unplugging it to the user's own settings
+        overrides.profile = None;
+        assert_eq!(overrides.preset().map(String::as_str), Some("headphones"));
+        overrides.device = None;
+        assert_eq!(overrides.preset(), None);
+    }
+
+    fn spatial(width: f32, balance: f32, mono: bool, swap: bool) -> SpatialSettings {
+        SpatialSettings { width, balance, mono, swap, ..SpatialSettings::default() }
+    }
+
+    #[test]
+    fn routingMatrix() {
+        assert_eq!(spatial(1., 0., false, false).routingMatrix(), [[1., 0.], [0., 1.]]);
+        assert!(spatial(1., 0., false, false).isNeutral());
+        assert_eq!(spatial(1.5, 0., false, false).routingMatrix(), [[1.25, -0.25], [-0.25, 1.25]]);
+        assert_eq!(spatial(0.5, 0., false, false).routingMatrix(), [[0.75, 0.25], [0.25, 0.75]]);
+        // mono ignores the width
+        assert_eq!(spatial(1.5, 0., true, false).routingMatrix(), [[0.5, 0.5], [0.5, 0.5]]);
+        assert_eq!(spatial(1., 0., false, true).routingMatrix(), [[0., 1.], [1., 0.]]);
+        // balance only ever turns a side down
+        assert_eq!(spatial(1., 0.5, false, false).routingMatrix(), [[0.5, 0.], [0., 1.]]);
+        assert_eq!(spatial(1., -1., false, false).routingMatrix(), [[1., 0.], [0., 0.]]);
+        assert_eq!(spatial(3., 0., false, false).routingMatrix(), spatial(2., 0., false, false).routingMatrix());
+    }
+
+    #[test]
+    fn routingConfig() {
+        assert_eq!(spatial(1., 0., false, false).toConfig(), "");
+        assert_eq!(spatial(1.5, 0., false, false).toConfig(), "Copy: L=1.25*L-0.25*R R=-0.25*L+1.25*R\n");
+        assert_eq!(spatial(0.5, 0., false, false).toConfig(), "Copy: L=0.75*L+0.25*R R=0.25*L+0.75*R\n");
+        assert_eq!(spatial(1., 0., false, true).toConfig(), "Copy: L=1*R R=1*L\n");
+        assert_eq!(spatial(1., 1., false, false).toConfig(), "Copy: L=0 R=1*R\n");
+        assert_eq!(spatial(2., 0., false, true).toConfig(), "Copy: L=-0.5*L+1.5*R R=1.5*L-0.5*R\n");
+        assert_eq!(spatial(2., 0., false, false).maxBoost(), 20. * 2f64.log10());
+    }
+
+    #[test]
+    fn routingMatrixInverts() {
+        for (width, balance, mono, swap) in [(1.5, -0.25, false, true), (0.5, 0.5, false, false), (1., 1., false, false), (2., 0., false, true), (1., -0.75, true, false)] {
+            let settings = spatial(width, balance, mono, swap);
+            assert_eq!(SpatialSettings::fromRoutingMatrix(settings.routingMatrix()), Some(settings));
+        }
+
+        assert_eq!(SpatialSettings::fromRoutingMatrix([[1., 0.], [1., 0.]]), None);
+        assert_eq!(SpatialSettings::fromRoutingMatrix([[0., 0.], [0., 0.]]), None);
+    }
+
+    // the Copy lines come back as the settings they were written from, crossfeed included
+    #[test]
+    fn spatialRoundTrips() {
+        let spatial = SpatialSettings { crossfeed: true, crossfeedLevel: -6., crossfeedCutoff: 650., ..spatial(1.5, -0.25, false, true) };
+
+        let mut linked = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![curve(Speaker::FrontLeft), curve(Speaker::FrontRight)]);
+        linked.spatial = spatial;
+        assert_eq!(roundTrip(&linked).settings, linked);
+
+        let mut right = curve(Speaker::FrontRight);
+        right.filters.truncate(3);
+        let mut split = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![curve(Speaker::FrontLeft), right]);
+        split.spatial = SpatialSettings { crossfeed: false, crossfeedLevel: -4.5, crossfeedCutoff: 700., ..spatial };
+        assert_eq!(roundTrip(&split).settings, split);
+
+        // the virtual channels crossfeed adds are kept out of the speakers' own filters
+        let mut surround = EqualiserSettings::fromChannels(ChannelLayout::Surround51, vec![curve(Speaker::FrontLeft)]);
+        surround.linked = true;
+        let mut surround = surround.synchronised();
+        surround.spatial = SpatialSettings { crossfeed: true, ..SpatialSettings::default() };
+        let config = surround.toConfig(48_000.);
+        assert!(config.contains("Channel: L R C SUB SL SR\n") && !config.contains("Channel: all"), "{}", config);
+        assert_eq!(roundTrip(&surround).settings, surround);
+    }
+
+    // the preamp has to cover the compensation it asks for itself, at the level it ends up at
+    #[test]
+    fn headroomSettlesUnderLoudness() {
+        let channel = EqualiserChannelSettings {
+            filters: vec![filter(FilterType::Peak, 60., 8., FilterWidth::Q(1.))],
+            autoHeadroom: true,
+            headroomMargin: 1.,
+            ..EqualiserChannelSettings::new(Speaker::FrontLeft)
+        };
+        let mut settings = EqualiserSettings::fromChannels(ChannelLayout::Stereo, vec![channel.clone(), channel.linkedTo(&EqualiserChannelSettings::new(Speaker::FrontRight))]);
+        settings.loudness = LoudnessSettings { enabled: true, referenceLevel: 83. };
+
+        let settled = settings.clone().withAutoHeadroom(48_000.);
+        let preamp = settled.channels[0].preamp;
+        let compensated = &settled.compensated(48_000.).channels[0];
+        assert!(compensated.filters.len() > 1, "no compensation at {} dB", preamp);
+        assert!((preamp as f64 + maxBoost(compensated, 48_000.) + 1.).abs() < HEADROOM_TOLERANCE as f64, "preamp {}", preamp);
+
+        settings.loudness.enabled = false;
+        assert!(settings.withAutoHeadroom(48_000.).channels[0].preamp > preamp);
+    }
+
+    fn migrated(json: &str) -> EqualiserSettings {
+        let settings: EqualiserSettings = serde_json::from_str(json).unwrap();
+        // saving and loading again changes nothing
+        let saved = serde_json::to_string(&settings).unwrap();
+        assert_eq!(serde_json::from_str::<EqualiserSettings>(&saved).unwrap(), settings);
+
+        settings
+    }
+
+    // the five fixed gains from before the filter list
+    #[test]
+    fn migratesFixedGains() {
+        let settings = migrated(
+            r#"[
+                {"preamp": -3, "channelLeft": true, "bassGain": 4, "lowGain": 0, "midGain": -2, "highGain": 0, "trebleGain": 1.5},
+                {"preamp": -3, "channelLeft": false, "bassGain": 4, "lowGain": 0, "midGain": -2, "highGain": 0, "trebleGain": 1.5}
+            ]"#,
+        );
+
+        assert_eq!(settings.layout, ChannelLayout::Stereo);
+        assert!(settings.linked);
+        assert_eq!(settings.channels[0].speaker, Speaker::FrontLeft);
+        assert_eq!(settings.channels[1].speaker, Speaker::FrontRight);
+        assert_eq!(settings.channels[0].preamp, -3.);
+        assert_eq!(settings.channels[0].filters[0], filter(FilterType::LowShelf, 250., 4., FilterWidth::Q(0.707)));
+        assert_eq!(settings.channels[0].filters[2], filter(FilterType::Peak, 750., -2., FilterWidth::Q(3.)));
+        assert_eq!(settings.channels[0].filters[4], filter(FilterType::HighShelf, 1250., 1.5, FilterWidth::Q(0.707)));
+        assert_eq!(settings.channels[0].filters, settings.channels[1].filters);
+    }
+
+    // the left/right tuple of filter lists, linked added later
+    #[test]
+    fn migratesFilterLists() {
+        let settings = migrated(
+            r#"[
+                {"preamp": -2, "channelLeft": true, "filters": [{"filterType": "Peak", "frequency": 1000, "gain": 3, "width": {"Q": 1.41}, "enabled": true}]},
+                {"preamp": -1, "channelLeft": false, "filters": []}
+            ]"#,
+        );
+
+        // the channels differ, linking them would overwrite the right one with the left
+        assert_eq!(settings.layout, ChannelLayout::Stereo);
+        assert!(!settings.linked);
+        assert_eq!(settings.clone().synchronised(), settings);
+        assert_eq!(settings.channels[0].filters, vec![filter(FilterType::Peak, 1000., 3., FilterWidth::Q(1.41))]);
+        assert_eq!((settings.channels[1].speaker, settings.channels[1].preamp), (Speaker::FrontRight, -1.));
+        assert_eq!(settings.channels[0].headroomMargin, 0.5);
+        assert_eq!(settings.dynamics, DynamicsSettings::default());
+    }
+
+    // the tuple with linked, routing and loudness, right before the channel map
+    #[test]
+    fn migratesStereoTuple() {
+        let settings = migrated(
+            r#"[
+                {"preamp": -4, "channelLeft": true, "filters": [], "graphicEq": [{"frequency": 100, "gain": 2}], "convolution": null, "autoHeadroom": true, "headroomMargin": 1},
+                {"preamp": -5, "channelLeft": false, "filters": [], "graphicEq": [], "convolution": "C:/IRs/room.wav", "autoHeadroom": false, "headroomMargin": 0.5},
+                false,
+                {"crossfeed": true, "crossfeedLevel": -6, "crossfeedCutoff": 650, "balance": 0.1, "mono": false, "swap": false, "width": 1.2},
+                {"enabled": true, "referenceLevel": 80}
+            ]"#,
+        );
+
+        assert!(!settings.linked);
+        assert!(settings.channels[0].autoHeadroom);
+        assert_eq!(settings.channels[0].graphicEq, vec![GraphicEqPoint { frequency: 100., gain: 2. }]);
+        assert_eq!(settings.channels[1].convolution.as_deref(), Some("C:/IRs/room.wav"));
+        assert!(settings.spatial.crossfeed);
+        assert_eq!(settings.spatial.width, 1.2);
+        assert_eq!(settings.loudness, LoudnessSettings { enabled: true, referenceLevel: 80. });
+    }
+
+    // configs with channels missing, out of order or for other speakers still get one per speaker of the layout
+    #[test]
+    fn normalisesChannelMaps() {
+        let settings = migrated(r#"{"layout": "Surround51", "channels": [], "linked": false}"#);
+        assert_eq!(settings.channels.len(), 6);
+        assert!(!settings.toConfig(48_000.).is_empty());
+        assert!(!crate::formats::toEasyEffects(&settings, 48_000.).contents.is_empty());
+        assert!(!crate::pipewire::filterChain(&settings, 48_000.).config().is_empty());
+
+        let settings = migrated(
+            r#"{"layout": "Surround51", "linked": true, "channels": [
+                {"speaker": "FrontLeft", "preamp": -6, "filters": [{"filterType": "Peak", "frequency": 80, "gain": 6, "width": {"Q": 1}, "enabled": true}]},
+                {"speaker": "SideRight", "preamp": -1, "filters": []},
+                {"speaker": "BackLeft", "preamp": -9, "filters": []}
+            ]}"#,
+        );
+        let speakers: Vec<Speaker> = settings.channels.iter().map(|c| c.speaker).collect();
+        assert_eq!(speakers, ChannelLayout::Surround51.speakers());
+        assert_eq!(settings.channel(Speaker::SideRight).unwrap().preamp, -1.);
+        // missing speakers start out like the first channel while linked
+        assert_eq!(settings.channel(Speaker::Centre).unwrap().filters, settings.channels[0].filters);
+        assert!(!settings.toConfig(48_000.).is_empty());
+    }
 }
@@ -1,121 +1,513 @@
-use std::{thread, time::{Duration, Instant}, vec};
+use std::{collections::VecDeque, thread, time::{Duration, Instant}, vec};
 
 use audioviz::spectrum::{config::StreamConfig, stream::Stream, Frequency};
 use fast_math::log2;
 use tauri::{AppHandle, Emitter};
-use wasapi::{initialize_mta, AudioClient, Direction, ShareMode, WaveFormat};
+use wasapi::{get_default_device, initialize_mta, AudioClient, Direction, ShareMode, WaveFormat};
 
-use crate::{structs::VisualiserType, FrequencyInterval};
+use crate::{structs::{BandAggregation, SpatialSmoothing, VisualiserType}, FrequencyInterval};
 
 
 
+pub fn aggregate(volumes: &[f32], aggregation: BandAggregation) -> f32 {
+    if volumes.is_empty() {
+        return 0.;
+    }
+
+    match aggregation {
+        BandAggregation::Mean => volumes.iter().sum::<f32>() / volumes.len() as f32,
+        BandAggregation::Max => volumes.iter().copied().fold(f32::MIN, f32::max),
+        BandAggregation::Rms => (volumes.iter().map(|v| v * v).sum::<f32>() / volumes.len() as f32).sqrt(),
+        BandAggregation::Energy => volumes.iter().map(|v| v * v).sum(),
+    }
+}
+
+// linear interpolation between the two bins surrounding `freq`, used for bands too narrow to contain a bin of their own
+pub fn interpolateVolume(data: &[Frequency], freq: f32) -> f32 {
+    let after = data.partition_point(|f| f.freq < freq);
+
+    match (after.checked_sub(1).and_then(|i| data.get(i)), data.get(after)) {
+        (Some(a), Some(b)) if b.freq > a.freq => a.volume + (b.volume - a.volume) * (freq - a.freq) / (b.freq - a.freq),
+        (Some(a), _) => a.volume,
+        (None, Some(b)) => b.volume,
+        (None, None) => 0.,
+    }
+}
+
+pub fn makeBands(data: &[Frequency], edges: &[f32], aggregation: BandAggregation) -> Vec<FrequencyInterval> {
+    let bandCount = edges.len().saturating_sub(1);
+
+    edges
+        .windows(2)
+        .enumerate()
+        .map(|(i, band)| {
+            let start = data.partition_point(|f| f.freq < band[0]);
+            // the last band also takes the bin sitting exactly on the upper edge
+            let end = if i + 1 == bandCount {
+                data.partition_point(|f| f.freq <= band[1])
+            } else {
+                data.partition_point(|f| f.freq < band[1])
+            }.max(start);
+
+            let volumes: Vec<f32> = data[start..end].iter().map(|f| f.volume).collect();
+            let volume = if volumes.is_empty() {
+                let centre = if band[0] > 0. { (band[0] * band[1]).sqrt() } else { (band[0] + band[1]) / 2. };
+                aggregate(&[interpolateVolume(data, centre)], aggregation)
+            } else {
+                aggregate(&volumes, aggregation)
+            };
+
+            FrequencyInterval {
+                index: i as u16,
+                volume,
+            }
+        })
+        .collect()
+}
+
 pub fn makeDistribution(data: &[Frequency], resolution: usize) -> Vec<FrequencyInterval> {
     if resolution == 0 {
         return vec![];
     }
 
-    let visualiserType = crate::VISUALISER_CONFIG.read().unwrap().visualiserType;
+    let (visualiserType, aggregation, minFrequency, maxFrequency, mut bandEdges) = {
+        let settings = crate::VISUALISER_CONFIG.read().unwrap();
+        (settings.visualiserType, settings.bandAggregation, settings.minFrequency, settings.maxFrequency, settings.bandEdges.clone())
+    };
+
+    if visualiserType != VisualiserType::Custom && !(minFrequency > 0. && maxFrequency > minFrequency) {
+        return (0..resolution).map(|i| FrequencyInterval { index: i as u16, volume: 0. }).collect();
+    }
+
     match visualiserType {
         // Group by frequency
         VisualiserType::Linear1 => {
-            let mut last = 0.;
-            let freqStep = 20_000. / resolution as f32;
-            let mut v: Vec<Vec<f32>> = vec![vec![]];
-
-            for i in data {
-                if i.freq < last + freqStep {
-                    v.last_mut().unwrap().push(i.volume);
-                } else {
-                    v.push(vec![i.volume]);
-                    last += freqStep;
-                }
-            }
+            let freqStep = (maxFrequency - minFrequency) / resolution as f32;
+            let edges: Vec<f32> = (0..=resolution).map(|i| minFrequency + freqStep * i as f32).collect();
 
-            for _ in 0..resolution - v.len() {
-                v.push(vec![]);
-            }
-
-            v.iter()
-                .enumerate()
-                .map(|(i, interval)| FrequencyInterval {
-                    index: i as u16,
-                    volume: if interval.is_empty() {
-                        0.0
-                    } else {
-                        interval.iter().sum::<f32>() / interval.len() as f32
-                    },
-                })
-                .collect()
+            makeBands(data, &edges, aggregation)
         },
         // Group by amount
         VisualiserType::Linear2 => {
-            let mut result = Vec::with_capacity(resolution);
-            
-            let n = data.len();
-            let step = n as f32 / resolution as f32;
-
-            for i in 0..resolution {
-                let start_f = (i as f32) * step;
-                let end_f = ((i + 1) as f32) * step;
-                let start = start_f.floor() as usize;
-                let end = end_f.floor() as usize;
-
-                let start = start.min(n);
-                let end = end.min(n);
-
-                let volume = if start < end {
-                    let slice = &data[start..end];
-                    let sum: f32 = slice.iter().map(|f| f.volume).sum();
-
-                    sum / (slice.len() as f32)
-                } else { 0.0 };
-
-                result.push(FrequencyInterval {
-                    index: i as u16,
-                    volume: volume,
-                });
-            }
+            // fft bins arrive sorted by frequency, so the configured range is a contiguous slice
+            let rangeStart = data.partition_point(|f| f.freq < minFrequency);
+            let rangeEnd = data.partition_point(|f| f.freq <= maxFrequency).max(rangeStart);
+            let ranged = &data[rangeStart..rangeEnd];
+
+            // edges on the bins themselves give every band an equal share of them, with fewer bins than bands
+            // some edges repeat and those bands interpolate
+            let edges: Vec<f32> = match ranged.last() {
+                Some(last) => (0..=resolution).map(|i| ranged.get(i * ranged.len() / resolution).map_or(last.freq, |f| f.freq)).collect(),
+                None => (0..=resolution).map(|i| minFrequency + (maxFrequency - minFrequency) * i as f32 / resolution as f32).collect(),
+            };
 
-            result
+            makeBands(data, &edges, aggregation)
         },
         // Group by frequency, logarithmic
         VisualiserType::Log => {
-            let mut intervals = vec![FrequencyInterval { index: 0, volume: 0. }; resolution];
-
-            // the min frequency is experimented with a bit and (partially) prevents the left side of the visualiser from simply being completely flat
-            let min = log2(75.);
-            let max = log2(20_000.);
-            let range = max - min;
-
-            let mut lastIndex = 0;
-            let mut lastVolume = 0.;
-            let mut lastCount = 0;
-            for i in data {
-                let normalisedLogFreq = (log2(i.freq) - min) / range;
-                let mut index = (normalisedLogFreq * (resolution as f32 - 0.001)).floor() as usize;
-                if index >= resolution {
-                    index = resolution - 1;
-                }
+            let min = log2(minFrequency);
+            let range = log2(maxFrequency) - min;
+            let edges: Vec<f32> = (0..=resolution)
+                .map(|i| (min + range * i as f32 / resolution as f32).exp2())
+                .collect();
 
-                if index == lastIndex {
-                    lastVolume += i.volume;
-                    lastCount += 1;
-                } else {
-                    intervals[lastIndex].index = lastIndex as u16;
-                    intervals[lastIndex].volume = lastVolume / lastCount as f32;
+            makeBands(data, &edges, aggregation)
+        },
+        // Group by user supplied band edges
+        VisualiserType::Custom => {
+            bandEdges.retain(|edge| edge.is_finite() && *edge >= 0.);
+            bandEdges.sort_by(|a, b| a.total_cmp(b));
+            bandEdges.dedup();
 
-                    lastIndex = index;
-                    lastVolume = i.volume;
-                    lastCount = 1;
-                }
-                
-                intervals[lastIndex].index = lastIndex as u16;
-                intervals[lastIndex].volume = lastVolume / lastCount as f32;
+            makeBands(data, &bandEdges, aggregation)
+        }
+    }
+}
+
+pub fn gaussianSmooth(volumes: &[f32], radius: usize, sigma: f32) -> Vec<f32> {
+    let sigma = sigma.max(f32::EPSILON);
+    let kernel: Vec<f32> = (0..=radius).map(|k| (-((k * k) as f32) / (2. * sigma * sigma)).exp()).collect();
+
+    (0..volumes.len())
+        .map(|i| {
+            let start = i.saturating_sub(radius);
+            let end = (i + radius).min(volumes.len() - 1);
+
+            // the kernel is renormalised over the bands that actually exist, so the edges don't sag
+            let (sum, weight) = (start..=end).fold((0., 0.), |(sum, weight), j| {
+                let w = kernel[i.abs_diff(j)];
+                (sum + volumes[j] * w, weight + w)
+            });
+
+            sum / weight
+        })
+        .collect()
+}
+
+// quadratic Savitzky-Golay fit, the window shrinks towards the edges
+pub fn savitzkyGolaySmooth(volumes: &[f32], radius: usize) -> Vec<f32> {
+    (0..volumes.len())
+        .map(|i| {
+            let m = radius.min(i).min(volumes.len() - 1 - i) as f32;
+            if m < 1. {
+                return volumes[i];
             }
 
-            intervals
+            let norm = (2. * m + 1.) * (4. * m * m + 4. * m - 3.);
+            let base = 3. * (3. * m * m + 3. * m - 1.);
+
+            (i - m as usize..=i + m as usize)
+                .map(|j| {
+                    let k = i.abs_diff(j) as f32;
+                    volumes[j] * (base - 15. * k * k) / norm
+                })
+                .sum::<f32>()
+                .max(0.)
+        })
+        .collect()
+}
+
+// cava's "monstercat" style, every bar pulls its neighbours up with an exponential falloff, one pass from each side
+// carries the strongest pull along
+pub fn monstercatSmooth(volumes: &[f32], falloff: f32) -> Vec<f32> {
+    let falloff = falloff.max(1.);
+    let mut result = volumes.to_vec();
+
+    for i in 1..result.len() {
+        result[i] = result[i].max(result[i - 1] / falloff);
+    }
+    for i in (0..result.len().saturating_sub(1)).rev() {
+        result[i] = result[i].max(result[i + 1] / falloff);
+    }
+
+    result
+}
+
+pub fn smoothBands(bands: &mut [FrequencyInterval]) {
+    if bands.is_empty() {
+        return;
+    }
+
+    let (smoothing, radius, sigma, falloff) = {
+        let settings = crate::VISUALISER_CONFIG.read().unwrap();
+        (settings.spatialSmoothing, settings.smoothingRadius as usize, settings.smoothingSigma, settings.monstercatFalloff)
+    };
+
+    let volumes: Vec<f32> = bands.iter().map(|b| b.volume).collect();
+    let smoothed = match smoothing {
+        SpatialSmoothing::None => return,
+        SpatialSmoothing::Gaussian => gaussianSmooth(&volumes, radius, sigma),
+        SpatialSmoothing::SavitzkyGolay => savitzkyGolaySmooth(&volumes, radius),
+        SpatialSmoothing::Monstercat => monstercatSmooth(&volumes, falloff),
+    };
+
+    for (band, volume) in bands.iter_mut().zip(smoothed) {
+        band.volume = volume;
+    }
+}
+
+// 0 fps leaves the pace to the capture loop
+pub fn frameInterval(fps: u16) -> Duration {
+    match fps {
+        0 => Duration::ZERO,
+        fps => Duration::from_secs_f32(1. / fps as f32),
+    }
+}
+
+pub fn rmsDecibels(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+
+    let meanSquare = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+    10. * meanSquare.log10()
+}
+
+// digital silence counts whatever the threshold, -inf dB included
+pub fn isSilent(samples: &[f32], threshold: f32) -> bool {
+    let level = rmsDecibels(samples);
+    level == f32::NEG_INFINITY || level < threshold
+}
+
+#[cfg(windows)]
+pub fn isOnBattery() -> bool {
+    use windows_sys::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+    unsafe {
+        let mut status: SYSTEM_POWER_STATUS = std::mem::zeroed();
+        GetSystemPowerStatus(&mut status) != 0 && status.ACLineStatus == 0
+    }
+}
+
+#[cfg(not(windows))]
+pub fn isOnBattery() -> bool {
+    false
+}
+
+// the wallpaper counts as occluded when the foreground window is maximised or covers its whole monitor
+#[cfg(windows)]
+pub fn isOccluded() -> bool {
+    use windows_sys::Win32::{
+        Foundation::RECT,
+        Graphics::Gdi::{GetMonitorInfoW, MonitorFromWindow, MONITORINFO, MONITOR_DEFAULTTONEAREST},
+        UI::WindowsAndMessaging::{GetClassNameW, GetForegroundWindow, GetShellWindow, GetWindowRect, IsZoomed},
+    };
+
+    unsafe {
+        let foreground = GetForegroundWindow();
+        if foreground.is_null() || foreground == GetShellWindow() {
+            return false;
+        }
+
+        // the desktop itself lives in these windows
+        let mut className = [0u16; 32];
+        let length = GetClassNameW(foreground, className.as_mut_ptr(), className.len() as i32);
+        let className = String::from_utf16_lossy(&className[..length.max(0) as usize]);
+        if className == "Progman" || className == "WorkerW" {
+            return false;
+        }
+
+        if IsZoomed(foreground) != 0 {
+            return true;
+        }
+
+        let mut rect: RECT = std::mem::zeroed();
+        let mut monitorInfo: MONITORINFO = std::mem::zeroed();
+        monitorInfo.cbSize = std::mem::size_of::<MONITORINFO>() as u32;
+
+        if GetWindowRect(foreground, &mut rect) == 0
+            || GetMonitorInfoW(MonitorFromWindow(foreground, MONITOR_DEFAULTTONEAREST), &mut monitorInfo) == 0
+        {
+            return false;
+        }
+
+        rect.left <= monitorInfo.rcMonitor.left
+            && rect.top <= monitorInfo.rcMonitor.top
+            && rect.right >= monitorInfo.rcMonitor.right
+            && rect.bottom >= monitorInfo.rcMonitor.bottom
+    }
+}
+
+#[cfg(not(windows))]
+pub fn isOccluded() -> bool {
+    false
+}
+
+#[cfg(windows)]
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+// reads a REG_SZ value from HKEY_LOCAL_MACHINE, always from the 64 bit view
+#[cfg(windows)]
+pub fn readRegistryString(subKey: &str, value: &str) -> Option<String> {
+    use windows_sys::Win32::{Foundation::ERROR_SUCCESS, System::Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_SZ, RRF_SUBKEY_WOW6464KEY}};
+
+    let (subKey, value) = (wide(subKey), wide(value));
+    let mut buffer = [0u16; 1024];
+    let mut size = std::mem::size_of_val(&buffer) as u32;
+
+    let result = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            subKey.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_REG_SZ | RRF_SUBKEY_WOW6464KEY,
+            std::ptr::null_mut(),
+            buffer.as_mut_ptr().cast(),
+            &mut size,
+        )
+    };
+    if result != ERROR_SUCCESS {
+        return None;
+    }
+
+    let length = (size as usize / 2).saturating_sub(1);
+    Some(String::from_utf16_lossy(&buffer[..length]))
+}
+
+#[cfg(not(windows))]
+pub fn readRegistryString(_subKey: &str, _value: &str) -> Option<String> {
+    None
+}
+
+#[cfg(windows)]
+pub fn registrySubKeys(subKey: &str) -> Vec<String> {
+    use windows_sys::Win32::{Foundation::ERROR_SUCCESS, System::Registry::{RegCloseKey, RegEnumKeyExW, RegOpenKeyExW, HKEY, HKEY_LOCAL_MACHINE, KEY_READ, KEY_WOW64_64KEY}};
+
+    let subKey = wide(subKey);
+    let mut key: HKEY = std::ptr::null_mut();
+    let mut subKeys = vec![];
+
+    unsafe {
+        if RegOpenKeyExW(HKEY_LOCAL_MACHINE, subKey.as_ptr(), 0, KEY_READ | KEY_WOW64_64KEY, &mut key) != ERROR_SUCCESS {
+            return subKeys;
         }
+
+        let mut name = [0u16; 256];
+        for index in 0.. {
+            let mut length = name.len() as u32;
+            let result = RegEnumKeyExW(key, index, name.as_mut_ptr(), &mut length, std::ptr::null(), std::ptr::null_mut(), std::ptr::null_mut(), std::ptr::null_mut());
+            if result != ERROR_SUCCESS {
+                break;
+            }
+
+            subKeys.push(String::from_utf16_lossy(&name[..length as usize]));
+        }
+
+        RegCloseKey(key);
+    }
+
+    subKeys
+}
+
+#[cfg(not(windows))]
+pub fn registrySubKeys(_subKey: &str) -> Vec<String> {
+    vec![]
+}
+
+// Executable file name of the foreground window's process, None for our own windows or when it can't be queried
+#[cfg(windows)]
+pub fn foregroundExecutable() -> Option<String> {
+    use windows_sys::Win32::{
+        Foundation::CloseHandle,
+        System::Threading::{OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION},
+        UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId},
+    };
+
+    unsafe {
+        let window = GetForegroundWindow();
+        if window.is_null() {
+            return None;
+        }
+
+        let mut pid = 0u32;
+        GetWindowThreadProcessId(window, &mut pid);
+        if pid == 0 || pid == std::process::id() {
+            return None;
+        }
+
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process.is_null() {
+            return None;
+        }
+
+        let mut path = [0u16; 1024];
+        let mut length = path.len() as u32;
+        let ok = QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, path.as_mut_ptr(), &mut length) != 0;
+        CloseHandle(process);
+
+        if !ok {
+            return None;
+        }
+
+        let path = String::from_utf16_lossy(&path[..length as usize]);
+        std::path::Path::new(&path).file_name().map(|name| name.to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(not(windows))]
+pub fn foregroundExecutable() -> Option<String> {
+    None
+}
+
+#[cfg(windows)]
+pub fn defaultOutputDevice() -> Option<String> {
+    let _ = initialize_mta();
+
+    get_default_device(&Direction::Render).and_then(|device| device.get_id()).ok()
+}
+
+// PipeWire's sink name, through its PulseAudio interface
+#[cfg(not(windows))]
+pub fn defaultOutputDevice() -> Option<String> {
+    pactl(&["get-default-sink"]).map(|sink| sink.trim().to_string()).filter(|sink| !sink.is_empty())
+}
+
+// the rate the default output device mixes at in shared mode, which is what APO runs its filters at
+#[cfg(windows)]
+pub fn defaultOutputSampleRate() -> Option<u32> {
+    let _ = initialize_mta();
+
+    get_default_device(&Direction::Render)
+        .and_then(|device| device.get_iaudioclient())
+        .and_then(|client| client.get_mixformat())
+        .map(|format| format.get_samplespersec())
+        .ok()
+}
+
+// the rate PipeWire runs its graph at unless a stream asks for another, which is what the filter-chain runs at
+#[cfg(not(windows))]
+pub fn defaultOutputSampleRate() -> Option<u32> {
+    pactl(&["info"]).and_then(|info| pactlSampleRate(&info))
+}
+
+#[cfg(windows)]
+pub fn defaultOutputDeviceName() -> Option<String> {
+    let _ = initialize_mta();
+
+    get_default_device(&Direction::Render).and_then(|device| device.get_friendlyname()).ok()
+}
+
+#[cfg(not(windows))]
+pub fn defaultOutputDeviceName() -> Option<String> {
+    let sink = defaultOutputDevice()?;
+    pactl(&["list", "sinks"]).and_then(|sinks| pactlDescription(&sinks, &sink))
+}
+
+#[cfg(not(windows))]
+fn pactl(args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new("pactl").args(args).env("LC_ALL", "C").output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// "Default Sample Specification: float32le 2ch 48000Hz" in `pactl info`
+pub fn pactlSampleRate(info: &str) -> Option<u32> {
+    info.lines()
+        .find_map(|line| line.trim().strip_prefix("Default Sample Specification:"))?
+        .split_whitespace()
+        .find_map(|token| token.strip_suffix("Hz")?.parse().ok())
+}
+
+// the "Description:" of the sink named `sink` in `pactl list sinks`
+pub fn pactlDescription(sinks: &str, sink: &str) -> Option<String> {
+    sinks
+        .split("\n\n")
+        .find(|block| block.lines().any(|line| line.trim().strip_prefix("Name:").is_some_and(|name| name.trim() == sink)))?
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Description:"))
+        .map(|description| description.trim().to_string())
+}
+
+// taps are matched to the nearest beat, the median offset is robust against the odd missed or doubled tap
+pub fn estimateLatency(beatTimes: &[f64], tapTimes: &[f64]) -> Option<f64> {
+    let mut offsets: Vec<f64> = tapTimes
+        .iter()
+        .filter_map(|tap| {
+            beatTimes
+                .iter()
+                .map(|beat| tap - beat)
+                .min_by(|a, b| a.abs().total_cmp(&b.abs()))
+        })
+        .collect();
+
+    if offsets.is_empty() {
+        return None;
     }
+
+    offsets.sort_by(|a, b| a.total_cmp(b));
+    let middle = offsets.len() / 2;
+
+    Some(if offsets.len() % 2 == 0 {
+        (offsets[middle - 1] + offsets[middle]) / 2.
+    } else {
+        offsets[middle]
+    })
+}
+
+// the delay in ms stored for a calibration, taps ahead of the beat can't be compensated so they count as none
+pub fn latencyCompensation(beatTimes: &[f64], tapTimes: &[f64]) -> Option<u16> {
+    estimateLatency(beatTimes, tapTimes).map(|offset| offset.clamp(0., 500.).round() as u16)
 }
 
 pub fn audioCapture(appHandle: AppHandle) -> Result<(), Box<dyn std::error::Error>> {
@@ -143,12 +535,54 @@ pub fn audioCapture(appHandle: AppHandle) -> Result<(), Box<dyn std::error::Erro
     // Spectrum analysis
     let mut spec = Stream::new(StreamConfig::default());
     let mut last = Instant::now();
-    let target = Duration::from_millis(15);
+    let mut target = Duration::from_millis(15);
+    let mut lastThrottleCheck: Option<Instant> = None;
+
+    // Latency compensation, frames are held back until the audio catches up
+    let mut delay = Duration::ZERO;
+    let mut pendingFrames: VecDeque<(Instant, Vec<String>)> = VecDeque::new();
+
+    // Silence detection
+    let mut silentSince: Option<Instant> = None;
+    let mut isSilent = false;
 
     loop {
         thread::sleep(Duration::from_millis(5));
 
+        // power and occlusion state is comparatively expensive to query, so it's only refreshed once per second
+        if lastThrottleCheck.is_none_or(|t| t.elapsed() >= Duration::from_secs(1)) {
+            let outputDevice = crate::OUTPUT_DEVICE.read().unwrap().clone();
+            let settings = crate::VISUALISER_CONFIG.read().unwrap();
+            let throttled = settings.adaptiveFrameRate && (isOnBattery() || isOccluded());
+
+            target = frameInterval(if throttled { settings.throttledFps } else { settings.targetFps });
+            delay = Duration::from_millis(
+                outputDevice
+                    .and_then(|device| settings.latencyCompensation.get(&device).copied())
+                    .unwrap_or(0)
+                    .min(500)
+                    .into(),
+            );
+            lastThrottleCheck = Some(Instant::now());
+        }
+
+        while pendingFrames.front().is_some_and(|(analysed, _)| analysed.elapsed() >= delay) {
+            let (_, magnitudes) = pendingFrames.pop_front().unwrap();
+
+            if let Err(e) = appHandle.emit("spectrum", magnitudes) {
+                eprintln!("Failed to emit audio-spectrum event: {}", e);
+            }
+        }
+
         let (frames, flags) = capClient.read_from_device(&mut buffer)?;
+
+        // checked before anything can skip ahead, history from before the gap would otherwise bleed into the next frame
+        if flags.data_discontinuity {
+            eprintln!("Discontinuity detected – reset state.");
+            sampleBuffer.clear();
+            spec = Stream::new(StreamConfig::default());
+        }
+
         if frames > 0 {
             let bytes = &buffer[..(frames * waveform.get_blockalign()) as usize];
             let samples = unsafe {
@@ -164,29 +598,345 @@ pub fn audioCapture(appHandle: AppHandle) -> Result<(), Box<dyn std::error::Erro
         }
 
         if last.elapsed() >= target {
+            let (silenceThreshold, silenceTimeout) = {
+                let settings = crate::VISUALISER_CONFIG.read().unwrap();
+                (settings.silenceThreshold, settings.silenceTimeout)
+            };
+
+            // loopback capture delivers no frames at all while nothing is playing, which also counts as silence
+            if silenceTimeout > 0. && isSilent(&sampleBuffer, silenceThreshold) {
+                let since = *silentSince.get_or_insert_with(Instant::now);
+
+                if !isSilent && since.elapsed().as_secs_f32() >= silenceTimeout {
+                    isSilent = true;
+
+                    if let Err(e) = appHandle.emit("silence", ()) {
+                        eprintln!("Failed to emit silence event: {}", e);
+                    }
+                }
+            } else {
+                silentSince = None;
+
+                if isSilent {
+                    isSilent = false;
+
+                    if let Err(e) = appHandle.emit("resume", ()) {
+                        eprintln!("Failed to emit resume event: {}", e);
+                    }
+                }
+            }
+
+            if isSilent {
+                sampleBuffer.clear();
+                last = Instant::now();
+                continue;
+            }
+
             spec.push_data(sampleBuffer.clone());
             spec.update();
 
             let freqs = spec.get_frequencies();
 
             if freqs.len() > 0 {
-                let magnitudes: Vec<String> = makeDistribution(&freqs[0], crate::VISUALISER_CONFIG.read().unwrap().resolution.into())
+                let mut bands = makeDistribution(&freqs[0], crate::VISUALISER_CONFIG.read().unwrap().resolution.into());
+                smoothBands(&mut bands);
+
+                let magnitudes: Vec<String> = bands
                     .iter()
                     .map(|f| format!(r#"{{ "index": {}, "volume": {} }}"#, f.index, f.volume))
                     .collect();
 
-                if let Err(e) = appHandle.emit("spectrum", magnitudes.clone()) {
-                    eprintln!("Failed to emit audio-spectrum event: {}", e);
-                }
+                pendingFrames.push_back((Instant::now(), magnitudes));
 
                 sampleBuffer.clear();
                 last = Instant::now();
             }
         }
+    }
+}
 
-        if flags.data_discontinuity {
-            eprintln!("Discontinuity detected – reset state.");
-            sampleBuffer.clear();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an FFT's bins up to Nyquist, none of them silent
+    fn spectrum(sampleRate: f32, fftSize: usize) -> Vec<Frequency> {
+        (0..=fftSize / 2)
+            .map(|i| Frequency {
+                volume: 0.1 + (i % 7) as f32 / 10.,
+                freq: i as f32 * sampleRate / fftSize as f32,
+                position: 0.,
+            })
+            .collect()
+    }
+
+    fn linearEdges(min: f32, max: f32, bands: usize) -> Vec<f32> {
+        (0..=bands).map(|i| min + (max - min) * i as f32 / bands as f32).collect()
+    }
+
+    fn logEdges(min: f32, max: f32, bands: usize) -> Vec<f32> {
+        (0..=bands).map(|i| min * (max / min).powf(i as f32 / bands as f32)).collect()
+    }
+
+    fn assertNoEmptyBands(data: &[Frequency], edges: &[f32], context: &str) {
+        for aggregation in [BandAggregation::Mean, BandAggregation::Max, BandAggregation::Rms, BandAggregation::Energy] {
+            let bands = makeBands(data, edges, aggregation);
+            assert_eq!(bands.len(), edges.len() - 1, "{}", context);
+            for band in bands {
+                assert!(band.volume > 0., "{}, {:?}: band {} is empty", context, aggregation, band.index);
+            }
+        }
+    }
+
+    #[test]
+    fn noBandIsEmpty() {
+        for sampleRate in [22_050., 44_100., 48_000., 96_000., 192_000.] {
+            for fftSize in [256, 512, 1024, 2048, 4096, 8192] {
+                let data = spectrum(sampleRate, fftSize);
+
+                for bands in [1, 2, 7, 32, 100, 256, 512] {
+                    let context = format!("{} Hz, {} point FFT, {} bands", sampleRate, fftSize, bands);
+                    assertNoEmptyBands(&data, &linearEdges(20., 20_000., bands), &format!("linear, {}", context));
+                    assertNoEmptyBands(&data, &logEdges(20., 20_000., bands), &format!("log, {}", context));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn customEdgesAreNeverEmpty() {
+        let data = spectrum(48_000., 1024);
+        let edges: [&[f32]; 5] = [
+            // narrower than a bin
+            &[20., 21., 22., 23.],
+            // starting at DC
+            &[0., 10., 100., 1_000.],
+            // past Nyquist
+            &[10_000., 24_000., 30_000.],
+            // one wide band
+            &[0., 24_000.],
+            // landing on bins exactly
+            &[46.875, 93.75, 140.625],
+        ];
+
+        for edges in edges {
+            assertNoEmptyBands(&data, edges, &format!("edges {:?}", edges));
+        }
+    }
+
+    #[test]
+    fn interpolatesBetweenBins() {
+        let data = [
+            Frequency { volume: 1., freq: 100., position: 0. },
+            Frequency { volume: 3., freq: 200., position: 0. },
+        ];
+
+        assert_eq!(interpolateVolume(&data, 150.), 2.);
+        assert_eq!(interpolateVolume(&data, 50.), 1.);
+        assert_eq!(interpolateVolume(&data, 300.), 3.);
+        assert_eq!(interpolateVolume(&[], 100.), 0.);
+    }
+
+    #[test]
+    fn frameIntervals() {
+        assert_eq!(frameInterval(0), Duration::ZERO);
+        assert_eq!(frameInterval(1), Duration::from_secs(1));
+        assert_eq!(frameInterval(60), Duration::from_secs_f32(1. / 60.));
+        assert!(frameInterval(u16::MAX) > Duration::ZERO);
+    }
+
+    #[test]
+    fn silenceLevels() {
+        assert_eq!(rmsDecibels(&[]), f32::NEG_INFINITY);
+        assert_eq!(rmsDecibels(&[0.; 480]), f32::NEG_INFINITY);
+        assert_eq!(rmsDecibels(&[1., -1.]), 0.);
+        assert!((rmsDecibels(&[1e-4; 16]) + 80.).abs() < 1e-3);
+        let sine: Vec<f32> = (0..4800).map(|i| (i as f32 * std::f32::consts::TAU / 48.).sin()).collect();
+        assert!((rmsDecibels(&sine) + 3.0103).abs() < 0.01, "{} dB", rmsDecibels(&sine));
+
+        // no frames and all zeros are silent even with the threshold as low as it goes
+        for threshold in [-70., f32::MIN, f32::NEG_INFINITY] {
+            assert!(isSilent(&[], threshold));
+            assert!(isSilent(&[0.; 480], threshold));
+        }
+        assert!(isSilent(&[1e-4; 16], -70.));
+        assert!(!isSilent(&[1e-4; 16], -90.));
+        // the threshold itself isn't below it
+        assert!(!isSilent(&[1., -1.], 0.));
+    }
+
+    // a small xorshift, the sizes differ from case to case but every run sees the same ones
+    struct Sizes(u64);
+    impl Sizes {
+        fn next(&mut self, range: std::ops::RangeInclusive<usize>) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            range.start() + (self.0 % (range.end() - range.start() + 1) as u64) as usize
+        }
+    }
+
+    #[test]
+    fn noDistributionHasEmptyBands() {
+        let mut sizes = Sizes(0x5eed);
+        let original = crate::VISUALISER_CONFIG.read().unwrap().clone();
+
+        for case in 0..200 {
+            let sampleRate = [22_050., 44_100., 48_000., 96_000., 192_000.][sizes.next(0..=4)];
+            let fftSize = 1 << sizes.next(6..=13);
+            let resolution = sizes.next(1..=512);
+            let minFrequency = sizes.next(1..=500) as f32;
+            let maxFrequency = minFrequency + sizes.next(1..=30_000) as f32;
+            let bandEdges: Vec<f32> = (0..sizes.next(2..=40)).map(|_| sizes.next(0..=30_000) as f32).collect();
+            let data = spectrum(sampleRate, fftSize);
+
+            for visualiserType in [VisualiserType::Linear1, VisualiserType::Linear2, VisualiserType::Log, VisualiserType::Custom] {
+                for bandAggregation in [BandAggregation::Mean, BandAggregation::Max, BandAggregation::Rms, BandAggregation::Energy] {
+                    *crate::VISUALISER_CONFIG.write().unwrap() = crate::structs::VisualiserSettings {
+                        visualiserType,
+                        bandAggregation,
+                        minFrequency,
+                        maxFrequency,
+                        bandEdges: bandEdges.clone(),
+                        ..original.clone()
+                    };
+
+                    let context = format!(
+                        "case {}: {:?}, {:?}, {} Hz, {} point FFT, {} bands, {}-{} Hz, edges {:?}",
+                        case, visualiserType, bandAggregation, sampleRate, fftSize, resolution, minFrequency, maxFrequency, bandEdges
+                    );
+                    let bands = makeDistribution(&data, resolution);
+                    if visualiserType != VisualiserType::Custom {
+                        assert_eq!(bands.len(), resolution, "{}", context);
+                    }
+                    for band in bands {
+                        assert!(band.volume > 0., "{}: band {} is empty", context, band.index);
+                    }
+                }
+            }
         }
+
+        *crate::VISUALISER_CONFIG.write().unwrap() = original;
+    }
+
+    fn assertClose(actual: &[f32], expected: &[f32], context: &str) {
+        assert_eq!(actual.len(), expected.len(), "{}", context);
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).abs() < 1e-4, "{}: band {} is {}, expected {}", context, i, a, e);
+        }
+    }
+
+    #[test]
+    fn smoothersKeepFlatInputFlat() {
+        for len in [1, 2, 5, 64] {
+            let flat = vec![0.4; len];
+            assertClose(&gaussianSmooth(&flat, 3, 1.5), &flat, "Gaussian");
+            assertClose(&savitzkyGolaySmooth(&flat, 3), &flat, "Savitzky-Golay");
+            assertClose(&monstercatSmooth(&flat, 1.5), &flat, "Monstercat");
+        }
+    }
+
+    #[test]
+    fn gaussianSpreadsAnImpulseOverItsRadius() {
+        let mut impulse = vec![0.; 21];
+        impulse[10] = 1.;
+
+        let smoothed = gaussianSmooth(&impulse, 3, 1.5);
+        for (i, volume) in smoothed.iter().enumerate() {
+            if i.abs_diff(10) <= 3 {
+                assert!(*volume > 0., "band {} within the radius stays empty", i);
+            } else {
+                assert_eq!(*volume, 0., "band {} outside the radius", i);
+            }
+        }
+        // symmetric and still peaking on the impulse
+        assertClose(&smoothed[7..=10], &smoothed[10..=13].iter().rev().copied().collect::<Vec<_>>(), "symmetry");
+        assert!(smoothed.iter().all(|v| *v <= smoothed[10]));
+    }
+
+    #[test]
+    fn savitzkyGolayKeepsQuadraticPeaks() {
+        // a quadratic fit reproduces a parabola exactly, peak included, wherever the full window fits
+        let parabola: Vec<f32> = (0..21).map(|i| 1. - ((i as f32 - 10.) / 12.).powi(2)).collect();
+        let smoothed = savitzkyGolaySmooth(&parabola, 3);
+        assertClose(&smoothed[3..18], &parabola[3..18], "parabola");
+
+        // while a lone spike gets flattened over the window
+        let mut impulse = vec![0.; 21];
+        impulse[10] = 1.;
+        let smoothed = savitzkyGolaySmooth(&impulse, 3);
+        assert!(smoothed[10] < 1. && smoothed[10] > 0.);
+        assert!(smoothed[..7].iter().chain(&smoothed[14..]).all(|v| *v == 0.));
+    }
+
+    #[test]
+    fn monstercatPullsNeighboursUp() {
+        let mut impulse = vec![0.; 9];
+        impulse[4] = 1.;
+
+        let expected: Vec<f32> = (0..9i32).map(|i| 2f32.powi(-(i - 4).abs())).collect();
+        assertClose(&monstercatSmooth(&impulse, 2.), &expected, "impulse");
+
+        // the same as pulling from every bar to every other, peaks untouched
+        let mut sizes = Sizes(0xface);
+        let volumes: Vec<f32> = (0..50).map(|_| sizes.next(0..=1000) as f32 / 1000.).collect();
+        let expected: Vec<f32> = (0..volumes.len())
+            .map(|i| volumes.iter().enumerate().map(|(j, v)| v / 1.5f32.powi(i.abs_diff(j) as i32)).fold(0., f32::max))
+            .collect();
+        assertClose(&monstercatSmooth(&volumes, 1.5), &expected, "random bars");
+    }
+
+    // a click every half second from 1 s on
+    fn beats() -> Vec<f64> {
+        (0..16).map(|i| 1000. + i as f64 * 500.).collect()
+    }
+
+    #[test]
+    fn estimatesLatencyFromTheMedianTap() {
+        let beats = beats();
+
+        let taps: Vec<f64> = beats.iter().map(|beat| beat + 120.).collect();
+        assert_eq!(estimateLatency(&beats, &taps), Some(120.));
+
+        // a missed tap, a doubled one and a stray one far off don't move the median
+        let mut taps: Vec<f64> = beats.iter().skip(1).map(|beat| beat + 80.).collect();
+        taps.push(beats[5] + 95.);
+        taps.push(beats[9] + 240.);
+        taps.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!(estimateLatency(&beats, &taps), Some(80.));
+
+        // an even number of taps lands between the middle two
+        assert_eq!(estimateLatency(&beats, &[beats[0] + 100., beats[1] + 110.]), Some(105.));
+
+        // taps are matched to the nearest beat, not the one before
+        assert_eq!(estimateLatency(&beats, &[beats[3] - 30.]), Some(-30.));
+
+        assert_eq!(estimateLatency(&beats, &[]), None);
+        assert_eq!(estimateLatency(&[], &taps), None);
+    }
+
+    #[test]
+    fn calibratesWithinTheCompensationRange() {
+        let beats = beats();
+        let tapsAt = |offset: f64| beats.iter().map(|beat| beat + offset).collect::<Vec<_>>();
+
+        assert_eq!(latencyCompensation(&beats, &tapsAt(87.4)), Some(87));
+        assert_eq!(latencyCompensation(&beats, &tapsAt(87.6)), Some(88));
+        // tapping ahead of the clicks means there's nothing to hold back
+        assert_eq!(latencyCompensation(&beats, &tapsAt(-40.)), Some(0));
+        assert_eq!(latencyCompensation(&[1000.], &[1800.]), Some(500));
+        assert_eq!(latencyCompensation(&beats, &[]), None);
+    }
+
+    #[test]
+    fn readsPactlOutput() {
+        let info = "Server Name: PulseAudio (on PipeWire 1.0.5)\nDefault Sample Specification: float32le 2ch 44100Hz\nDefault Channel Map: front-left,front-right\n";
+        assert_eq!(pactlSampleRate(info), Some(44_100));
+        assert_eq!(pactlSampleRate("Server Name: PulseAudio\n"), None);
+
+        let sinks = "Sink #46\n\tState: RUNNING\n\tName: alsa_output.usb-headphones\n\tDescription: USB Headphones\n\nSink #52\n\tName: alsa_output.pci-speakers\n\tDescription: Built-in Audio Analog Stereo\n";
+        assert_eq!(pactlDescription(sinks, "alsa_output.pci-speakers").as_deref(), Some("Built-in Audio Analog Stereo"));
+        assert_eq!(pactlDescription(sinks, "alsa_output.usb-headphones").as_deref(), Some("USB Headphones"));
+        assert_eq!(pactlDescription(sinks, "alsa_output.usb"), None);
     }
 }
@@ -3,28 +3,174 @@ export interface BackgroundElements {
     image: HTMLImageElement | null;
 };
 
-export type GainType = `preamp` | `bassGain` | `lowGain` | `midGain` | `highGain` | `trebleGain`;
+export type FilterType = `Peak` | `LowShelf` | `HighShelf` | `LowPass` | `HighPass` | `Notch` | `BandPass` | `AllPass`;
+export type FilterWidth = { Q: number } | { Slope: number };
+export interface Filter {
+    filterType: FilterType;
+    frequency: number;
+    gain: number;
+    width: FilterWidth;
+    enabled: boolean;
+};
+export interface GraphicEqPoint {
+    frequency: number;
+    gain: number;
+};
+export type GraphicEqImport = `Keep` | `Fit`;
+export type TargetCurve = `Flat` | `HarmanOverEar` | { Custom: GraphicEqPoint[] };
+export interface FitLimits {
+    minFrequency: number;
+    maxFrequency: number;
+    maxGain: number;
+    minQ: number;
+    maxQ: number;
+};
+export type EqualiserFormat = `EasyEffects` | `CamillaDsp`;
+export type Speaker = `FrontLeft` | `FrontRight` | `Centre` | `Lfe` | `SideLeft` | `SideRight` | `BackLeft` | `BackRight`;
+export type ChannelLayout = `Stereo` | `Surround51` | `Surround71`;
 export interface EqualiserChannelSettings {
+    speaker: Speaker;
     preamp: number;
-    channelLeft: boolean;
-    bassGain: number;
-    lowGain: number;
-    midGain: number;
-    highGain: number;
-    trebleGain: number;
+    filters: Filter[];
+    graphicEq: GraphicEqPoint[];
+    convolution: string | null;
+    autoHeadroom: boolean;
+    headroomMargin: number;
+    trim: number;
+    delay: number;
 };
 
 export type BarsColour = [number, number, number, number];
-export type VisualiserType = `Linear1` | `Linear2` | `Log`;
+export type VisualiserType = `Linear1` | `Linear2` | `Log` | `Custom`;
+export type BandAggregation = `Mean` | `Max` | `Rms` | `Energy`;
+export type SpatialSmoothing = `None` | `Gaussian` | `SavitzkyGolay` | `Monstercat`;
 export interface VisualiserSettings {
     barsColour: BarsColour;
     visualiserType: VisualiserType;
     useDesktopBackground: boolean;
     resolution: number;
     screen: string | undefined;
+    targetFps: number;
+    throttledFps: number;
+    adaptiveFrameRate: boolean;
+    silenceThreshold: number;
+    silenceTimeout: number;
+    minFrequency: number;
+    maxFrequency: number;
+    bandEdges: number[];
+    bandAggregation: BandAggregation;
+    spatialSmoothing: SpatialSmoothing;
+    smoothingRadius: number;
+    smoothingSigma: number;
+    monstercatFalloff: number;
+    latencyCompensation: Record<string, number>;
 }
 
-export type EqualiserSettings = [EqualiserChannelSettings, EqualiserChannelSettings];
-export type Configs = [EqualiserSettings, VisualiserSettings];
+export interface SpatialSettings {
+    crossfeed: boolean;
+    crossfeedLevel: number;
+    crossfeedCutoff: number;
+    balance: number;
+    mono: boolean;
+    swap: boolean;
+    width: number;
+};
+export interface LoudnessSettings {
+    enabled: boolean;
+    referenceLevel: number;
+};
+export interface Compressor {
+    enabled: boolean;
+    threshold: number;
+    ratio: number;
+    attack: number;
+    release: number;
+    knee: number;
+    makeupGain: number;
+};
+export interface Limiter {
+    enabled: boolean;
+    threshold: number;
+    release: number;
+};
+export interface DynamicsSettings {
+    compressor: Compressor;
+    limiter: Limiter;
+};
+export interface DynamicsSupport {
+    compressor: boolean;
+    limiter: boolean;
+};
+export interface EqualiserSettings {
+    layout: ChannelLayout;
+    channels: EqualiserChannelSettings[]; // in the layout's order
+    linked: boolean;
+    spatial: SpatialSettings;
+    loudness: LoudnessSettings;
+    dynamics: DynamicsSettings;
+};
+export interface EqualiserBackendSettings {
+    configDirOverride: string | null;
+    deviceBindings: Record<string, string>;
+};
+export interface ImpulseResponseInfo {
+    path: string;
+    sampleRate: number;
+    channels: number;
+    length: number;
+    duration: number;
+    warnings: string[];
+};
+export type ComparisonSlot = `A` | `B`;
+export interface ComparisonStatus {
+    bypassed: boolean;
+    slot: ComparisonSlot;
+};
+export interface EqualiserStatus {
+    backend: string;
+    installed: boolean;
+    version: string | null;
+    configDir: string;
+    writable: boolean;
+    devices: string[];
+    dynamics: DynamicsSupport;
+    seamlessSwitching: boolean;
+};
+export interface ResponsePoint {
+    frequency: number;
+    magnitude: number;
+    phase: number;
+};
+export interface EqualiserResponse {
+    channels: Partial<Record<Speaker, ResponsePoint[]>>;
+};
+export interface AppProfile {
+    executable: string;
+    preset: string | null;
+    visualiserSettings: VisualiserSettings | null;
+};
+export interface AppProfileSettings {
+    enabled: boolean;
+    rules: AppProfile[];
+    fallback: AppProfile | null;
+};
+export type Configs = [EqualiserSettings, VisualiserSettings, EqualiserBackendSettings, AppProfileSettings];
+export interface EqualiserPreset {
+    name: string;
+    settings: EqualiserSettings;
+    builtIn: boolean;
+};
+export interface ApoImport {
+    settings: EqualiserSettings;
+    warnings: string[];
+};
+export interface MeasurementMatch {
+    preset: EqualiserPreset;
+    warnings: string[];
+};
+export interface EqualiserExport {
+    contents: string;
+    warnings: string[];
+};
 
 export type CanvasPosition = [{ x: number, y: number }, { width: number, height: number }];